@@ -0,0 +1,297 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+
+use crate::{Brightness, CircuitPlayground, Color, CpError, SwitchPosition, TapState};
+
+// A call queued for the worker thread.
+type Job = Box<dyn FnOnce(&mut CircuitPlayground) + Send>;
+
+// What a `Pending` future and the job completing it share: the result once
+// there is one, and the waker of the task waiting for it.
+struct Shared<T> {
+    result: Option<Result<T, CpError>>,
+    waker: Option<Waker>,
+}
+
+/// A `CircuitPlayground` that can be used from async code, available with
+/// the `async` feature. The board is moved onto a worker thread, where calls
+/// run one at a time in the order they were made, and each method returns a
+/// `Pending` future that completes once the call has finished. Waiting for a
+/// reply, and the reply timeout, happen on the worker thread, so awaiting
+/// never blocks the executor.
+///
+/// This works with any executor, as it needs nothing from one beyond waking
+/// the task when the call completes.
+pub struct AsyncCircuitPlayground {
+    jobs: Option<Sender<Job>>,
+    worker: Option<JoinHandle<CircuitPlayground>>,
+}
+
+impl AsyncCircuitPlayground {
+    /// Moves `circuit_playground` onto a worker thread to use it from async
+    /// code.
+    pub fn new(circuit_playground: CircuitPlayground) -> AsyncCircuitPlayground {
+        let (jobs, queued) = mpsc::channel::<Job>();
+
+        let worker = thread::spawn(move || {
+            let mut circuit_playground = circuit_playground;
+            for job in queued {
+                job(&mut circuit_playground);
+            }
+            circuit_playground
+        });
+
+        AsyncCircuitPlayground {
+            jobs: Some(jobs),
+            worker: Some(worker),
+        }
+    }
+
+    /// Runs `f` with the board on the worker thread, for anything that
+    /// doesn't have an async method of its own.
+    pub fn run<T, F>(&self, f: F) -> Pending<T>
+        where T: Send + 'static, F: FnOnce(&mut CircuitPlayground) -> Result<T, CpError> + Send + 'static {
+        let shared = Arc::new(Mutex::new(Shared { result: None, waker: None }));
+        let completer = Completer { shared: Some(shared.clone()) };
+
+        // If the worker has stopped, the job is dropped here instead and the
+        // completer fails the future.
+        if let Some(ref jobs) = self.jobs {
+            let _ = jobs.send(Box::new(move |circuit_playground: &mut CircuitPlayground| {
+                completer.complete(f(circuit_playground));
+            }));
+        }
+
+        Pending { shared }
+    }
+
+    /// Waits for the calls already made to finish and hands back the
+    /// `CircuitPlayground`. Unlike awaiting, this blocks the calling thread,
+    /// for up to a reply timeout per call still queued.
+    pub fn into_inner(mut self) -> CircuitPlayground {
+        self.jobs.take();
+
+        match self.worker.take().expect("the worker thread was already joined").join() {
+            Ok(circuit_playground) => circuit_playground,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+
+    /// The async form of `CircuitPlayground::set_pixel`.
+    pub fn set_pixel<C: Into<Color>>(&self, index: u8, color: C) -> Pending<()> {
+        let color = color.into();
+        self.run(move |cp| cp.set_pixel(index, color))
+    }
+
+    /// The async form of `CircuitPlayground::show_pixels`.
+    pub fn show_pixels(&self) -> Pending<()> {
+        self.run(|cp| cp.show_pixels())
+    }
+
+    /// The async form of `CircuitPlayground::clear_pixels`.
+    pub fn clear_pixels(&self) -> Pending<()> {
+        self.run(|cp| cp.clear_pixels())
+    }
+
+    /// The async form of `CircuitPlayground::fill`.
+    pub fn fill<C: Into<Color>>(&self, color: C) -> Pending<()> {
+        let color = color.into();
+        self.run(move |cp| cp.fill(color))
+    }
+
+    /// The async form of `CircuitPlayground::set_brightness`.
+    pub fn set_brightness(&self, brightness: Brightness) -> Pending<()> {
+        self.run(move |cp| cp.set_brightness(brightness))
+    }
+
+    /// The async form of `CircuitPlayground::play_tone`.
+    pub fn play_tone(&self, frequency: u16, duration_ms: u16) -> Pending<()> {
+        self.run(move |cp| cp.play_tone(frequency, duration_ms))
+    }
+
+    /// The async form of `CircuitPlayground::stop_tone`.
+    pub fn stop_tone(&self) -> Pending<()> {
+        self.run(|cp| cp.stop_tone())
+    }
+
+    /// The async form of `CircuitPlayground::read_accelerometer`.
+    pub fn read_accelerometer(&self) -> Pending<(f32, f32, f32)> {
+        self.run(|cp| cp.read_accelerometer())
+    }
+
+    /// The async form of `CircuitPlayground::read_tap`.
+    pub fn read_tap(&self) -> Pending<TapState> {
+        self.run(|cp| cp.read_tap())
+    }
+
+    /// The async form of `CircuitPlayground::cap_read`.
+    pub fn cap_read(&self, pin: u8) -> Pending<i32> {
+        self.run(move |cp| cp.cap_read(pin))
+    }
+
+    /// The async form of `CircuitPlayground::read_temperature_c`.
+    pub fn read_temperature_c(&self) -> Pending<f64> {
+        self.run(|cp| cp.read_temperature_c())
+    }
+
+    /// The async form of `CircuitPlayground::read_light_level`.
+    pub fn read_light_level(&self) -> Pending<u16> {
+        self.run(|cp| cp.read_light_level())
+    }
+
+    /// The async form of `CircuitPlayground::read_sound_peak`.
+    pub fn read_sound_peak(&self, samples: usize) -> Pending<u16> {
+        self.run(move |cp| cp.read_sound_peak(samples))
+    }
+
+    /// The async form of `CircuitPlayground::button_a`.
+    pub fn button_a(&self) -> Pending<bool> {
+        self.run(|cp| cp.button_a())
+    }
+
+    /// The async form of `CircuitPlayground::button_b`.
+    pub fn button_b(&self) -> Pending<bool> {
+        self.run(|cp| cp.button_b())
+    }
+
+    /// The async form of `CircuitPlayground::slide_switch`.
+    pub fn slide_switch(&self) -> Pending<SwitchPosition> {
+        self.run(|cp| cp.slide_switch())
+    }
+}
+
+impl Drop for AsyncCircuitPlayground {
+    // The worker is left to finish the calls already made in the background,
+    // then drops the board, which stops everything it was doing. Waiting for
+    // it here would block the executor for up to a reply timeout per call.
+    fn drop(&mut self) {
+        self.jobs.take();
+        self.worker.take();
+    }
+}
+
+/// A call to an `AsyncCircuitPlayground` that hasn't finished yet, which
+/// completes with its result. The call is made whether or not this is
+/// awaited.
+#[must_use = "the result of the call is lost unless it is awaited"]
+pub struct Pending<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> Future for Pending<T> {
+    type Output = Result<T, CpError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+// Completes a `Pending` future from the worker thread. One dropped without
+// completing, because the worker stopped before getting to its call, fails
+// the future rather than leaving it waiting forever.
+struct Completer<T> {
+    shared: Option<Arc<Mutex<Shared<T>>>>,
+}
+
+impl<T> Completer<T> {
+    fn complete(mut self, result: Result<T, CpError>) {
+        self.finish(result);
+    }
+
+    fn finish(&mut self, result: Result<T, CpError>) {
+        let shared = match self.shared.take() {
+            Some(shared) => shared,
+            None => return,
+        };
+        let mut shared = shared.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        shared.result = Some(result);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        let stopped = io::Error::new(io::ErrorKind::BrokenPipe, "the worker thread stopped before making the call");
+        self.finish(Err(CpError::Io(stopped)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::task::Wake;
+    use std::thread::Thread;
+    use std::time::{Duration, Instant};
+
+    use firmata::{END_SYSEX, START_SYSEX};
+
+    use super::*;
+    use crate::{MockTransport, CP_COMMAND, CP_PIXEL_SHOW};
+
+    // Wakes a task by unparking the thread running it.
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // Runs `future` to completion on this thread, the least an executor can do.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn calls_run_in_order_and_return_their_errors() {
+        let mock = MockTransport::new();
+        let board = AsyncCircuitPlayground::new(CircuitPlayground::with_transport(mock.clone()));
+
+        let set = board.set_pixel(10, Color::RED);
+        let show = board.show_pixels();
+        assert!(matches!(block_on(set), Err(CpError::InvalidPixel(10))));
+        block_on(show).unwrap();
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_PIXEL_SHOW, END_SYSEX]);
+
+        let cp = board.into_inner();
+        assert_eq!(cp.pixel_count(), 10);
+    }
+
+    #[test]
+    fn dropping_the_board_does_not_wait_for_queued_calls() {
+        let board = AsyncCircuitPlayground::new(CircuitPlayground::with_transport(MockTransport::new()));
+
+        // Nothing answers, so the read waits out the whole reply timeout.
+        let read = board.read_accelerometer();
+        let started = Instant::now();
+        drop(board);
+        assert!(started.elapsed() < Duration::from_millis(500));
+
+        assert!(matches!(block_on(read), Err(CpError::Timeout)));
+    }
+}
@@ -0,0 +1,180 @@
+use std::io::Write;
+
+use crate::{Brightness, CircuitPlayground, Color, CpError};
+use crate::{CP_PIXEL_BRIGHTNESS, CP_PIXEL_CLEAR, CP_PIXEL_SET, CP_PIXEL_SHOW};
+
+/// A batch of commands that are sent to the board in a single write when
+/// `commit` is called, returned by `CircuitPlayground::batch`. Sending a
+/// whole animation frame this way is much faster than one write per command.
+///
+/// Commands are checked as they are added, and the first invalid one is
+/// returned by `commit` without anything being sent.
+#[must_use = "nothing is sent until the batch is committed"]
+pub struct Batch<'a> {
+    circuit_playground: &'a mut CircuitPlayground,
+    buf: Vec<u8>,
+    error: Option<CpError>,
+    // The colors the NeoPixels will be left set to once the batch is sent.
+    pixels: Vec<Color>,
+    // The brightness they will be left at, for `reconnect` to restore.
+    brightness: Option<Brightness>,
+}
+
+impl<'a> Batch<'a> {
+    // Starts an empty batch.
+    pub(crate) fn new(circuit_playground: &'a mut CircuitPlayground) -> Batch<'a> {
+        let pixels = circuit_playground.pixels.clone();
+        let brightness = circuit_playground.brightness;
+
+        Batch {
+            circuit_playground,
+            buf: vec![],
+            error: None,
+            pixels,
+            brightness,
+        }
+    }
+
+    /// Adds `CircuitPlayground::set_pixel` to the batch.
+    pub fn set_pixel<C: Into<Color>>(self, index: u8, color: C) -> Batch<'a> {
+        if let Err(err) = self.circuit_playground.check_pixel(index) {
+            return self.fail(err);
+        }
+
+        let color = color.into();
+        let data = color.to_firmata_bytes_in(self.circuit_playground.color_order);
+
+        let mut batch = self.push(CP_PIXEL_SET, &[index, data[0], data[1], data[2], data[3]]);
+        batch.pixels[index as usize] = color;
+        batch
+    }
+
+    /// Adds `CircuitPlayground::clear_pixels` to the batch.
+    pub fn clear(self) -> Batch<'a> {
+        let mut batch = self.push(CP_PIXEL_CLEAR, &[]);
+        batch.pixels.iter_mut().for_each(|color| *color = Color::BLACK);
+        batch
+    }
+
+    /// Adds `CircuitPlayground::set_brightness` to the batch.
+    pub fn set_brightness(self, brightness: Brightness) -> Batch<'a> {
+        let mut batch = self.push(CP_PIXEL_BRIGHTNESS, &[brightness.level()]);
+        batch.brightness = Some(brightness);
+        batch
+    }
+
+    /// Adds `CircuitPlayground::show_pixels` to the batch.
+    pub fn show(self) -> Batch<'a> {
+        self.push(CP_PIXEL_SHOW, &[])
+    }
+
+    /// Sends every command in the batch in one write, or returns the first
+    /// invalid command's error without sending anything.
+    pub fn commit(self) -> Result<(), CpError> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+
+        self.circuit_playground.write_with_retry(&self.buf)?;
+        self.circuit_playground.board.connection.flush()?;
+
+        self.circuit_playground.pixels = self.pixels;
+        self.circuit_playground.brightness = self.brightness;
+        Ok(())
+    }
+
+    fn push(mut self, sub: u8, data: &[u8]) -> Batch<'a> {
+        crate::frame_cp_command(&mut self.buf, sub, data);
+        self
+    }
+
+    // Keeps the first error, as later commands may depend on earlier ones.
+    fn fail(mut self, err: CpError) -> Batch<'a> {
+        if self.error.is_none() {
+            self.error = Some(err);
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::{Brightness, CircuitPlayground, Color, MockTransport};
+    use crate::{CP_COMMAND, CP_PIXEL_SET, CP_PIXEL_SHOW, END_SYSEX, START_SYSEX};
+
+    // A mock that also counts the writes made to it.
+    struct CountingTransport {
+        mock: MockTransport,
+        writes: Arc<AtomicUsize>,
+    }
+
+    impl Read for CountingTransport {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.mock.read(buf)
+        }
+    }
+
+    impl Write for CountingTransport {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            self.mock.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.mock.flush()
+        }
+    }
+
+    #[test]
+    fn commit_sends_the_whole_batch_in_one_write() {
+        let mock = MockTransport::new();
+        let writes = Arc::new(AtomicUsize::new(0));
+        let mut cp = CircuitPlayground::with_transport(CountingTransport { mock: mock.clone(), writes: writes.clone() });
+        let before = writes.load(Ordering::SeqCst);
+
+        cp.batch().set_pixel(0, Color::RED).set_pixel(1, Color::BLUE).show().commit().unwrap();
+        assert_eq!(writes.load(Ordering::SeqCst) - before, 1);
+
+        let (red, blue) = (Color::RED.to_firmata_bytes(), Color::BLUE.to_firmata_bytes());
+        assert_eq!(mock.take_written(), vec![
+            START_SYSEX, CP_COMMAND, CP_PIXEL_SET, 0, red[0], red[1], red[2], red[3], END_SYSEX,
+            START_SYSEX, CP_COMMAND, CP_PIXEL_SET, 1, blue[0], blue[1], blue[2], blue[3], END_SYSEX,
+            START_SYSEX, CP_COMMAND, CP_PIXEL_SHOW, END_SYSEX,
+        ]);
+        assert_eq!(&cp.pixels[..2], &[Color::RED, Color::BLUE]);
+    }
+
+    #[test]
+    fn invalid_batches_send_nothing() {
+        let mock = MockTransport::new();
+        let mut cp = CircuitPlayground::with_transport(mock.clone());
+        mock.take_written();
+
+        let result = cp.batch().set_pixel(0, Color::RED).set_pixel(10, Color::RED).show().commit();
+        assert!(matches!(result, Err(crate::CpError::InvalidPixel(10))));
+        assert!(mock.take_written().is_empty());
+        assert_eq!(cp.pixels[0], Color::BLACK);
+    }
+
+    #[test]
+    fn commit_records_brightness() {
+        let mut cp = CircuitPlayground::with_transport(MockTransport::new());
+        let brightness = Brightness::new(30).unwrap();
+
+        cp.batch().set_brightness(brightness).show().commit().unwrap();
+        assert_eq!(cp.brightness, Some(brightness));
+    }
+
+    #[test]
+    fn failed_batch_leaves_brightness_alone() {
+        let mut cp = CircuitPlayground::with_transport(MockTransport::new());
+
+        let result = cp.batch().set_brightness(Brightness::new(30).unwrap()).set_pixel(10, (255, 0, 0)).commit();
+        assert!(result.is_err());
+        assert_eq!(cp.brightness, None);
+    }
+}
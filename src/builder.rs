@@ -0,0 +1,250 @@
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use firmata::{Board, END_SYSEX, REPORT_FIRMWARE, START_SYSEX};
+use serial::prelude::*;
+use serial::{BaudRate, Baud57600, Bits8, ParityNone, Stop1, FlowNone};
+
+use crate::connection::{Connected, Watched};
+use crate::{CircuitPlayground, ColorOrder, CpError, DEFAULT_PIXEL_COUNT, DEFAULT_REPLY_TIMEOUT, Transport, find_circuit_playground, list_ports};
+
+// How long a single read from the serial port may block, in milliseconds.
+const SERIAL_READ_TIMEOUT_MS: i64 = 10;
+
+/// A builder for connecting to a Circuit Playground with non-default serial
+/// settings. The defaults match `CircuitPlayground::new`: 57600 baud, 8 data
+/// bits, no parity, one stop bit and a one second timeout.
+#[derive(Debug, Clone)]
+pub struct CircuitPlaygroundBuilder {
+    pub(crate) port: Option<String>,
+    baud_rate: BaudRate,
+    timeout: Duration,
+    pixel_count: u8,
+    color_order: ColorOrder,
+}
+
+impl Default for CircuitPlaygroundBuilder {
+    fn default() -> CircuitPlaygroundBuilder {
+        CircuitPlaygroundBuilder {
+            port: None,
+            baud_rate: Baud57600,
+            timeout: DEFAULT_REPLY_TIMEOUT,
+            pixel_count: DEFAULT_PIXEL_COUNT,
+            color_order: ColorOrder::Rgb,
+        }
+    }
+}
+
+impl CircuitPlaygroundBuilder {
+    /// Creates a new `CircuitPlaygroundBuilder` with the default settings.
+    pub fn new() -> CircuitPlaygroundBuilder {
+        CircuitPlaygroundBuilder::default()
+    }
+
+    /// Sets the serial port to connect to. If no port is set, `build` picks
+    /// the first Circuit Playground found by `list_ports`.
+    pub fn port(mut self, name: &str) -> CircuitPlaygroundBuilder {
+        self.port = Some(name.to_string());
+        self
+    }
+
+    /// Sets the baud rate, which must match the firmware.
+    pub fn baud_rate(mut self, baud_rate: BaudRate) -> CircuitPlaygroundBuilder {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    /// Sets how long methods that wait for a reply from the board wait
+    /// before returning `CpError::Timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> CircuitPlaygroundBuilder {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the number of NeoPixels, for boards other than the Circuit
+    /// Playground that run the same firmware. Pixel indexes are sent as a
+    /// single 7-bit byte, so counts are capped at 128, and at least one pixel
+    /// is assumed.
+    pub fn pixel_count(mut self, count: u8) -> CircuitPlaygroundBuilder {
+        self.pixel_count = count.clamp(1, 128);
+        self
+    }
+
+    /// Sets the order the color channels are sent in, for boards whose red
+    /// and green come out swapped. The default, `ColorOrder::Rgb`, is right
+    /// for the Circuit Playground.
+    pub fn color_order(mut self, order: ColorOrder) -> CircuitPlaygroundBuilder {
+        self.color_order = order;
+        self
+    }
+
+    /// Opens the serial port, connects to the board and resets it with
+    /// `CircuitPlayground::reset`. Returns `CpError::Timeout` if nothing on
+    /// the port answers the Firmata handshake within the timeout.
+    pub fn build(mut self) -> Result<CircuitPlayground, CpError> {
+        let port = match self.port {
+            Some(ref port) => port.clone(),
+            None => {
+                let ports = list_ports();
+                match find_circuit_playground(&ports) {
+                    Some(port) => port.name.clone(),
+                    None => return Err(CpError::NoBoardFound(ports.into_iter().map(|port| port.name).collect())),
+                }
+            }
+        };
+
+        let (board, connected) = self.open(&port)?;
+        let mut circuit_playground = CircuitPlayground::from_board(board, self.timeout, connected);
+        self.configure(&mut circuit_playground);
+        self.port = Some(port);
+        circuit_playground.builder = Some(self);
+        circuit_playground.reset()?;
+
+        Ok(circuit_playground)
+    }
+
+    // Applies the settings that are kept on the board itself rather than the
+    // connection, sizing the tracked pixel colors to the pixel count.
+    pub(crate) fn configure(&self, circuit_playground: &mut CircuitPlayground) {
+        circuit_playground.pixel_count = self.pixel_count;
+        circuit_playground.color_order = self.color_order;
+        circuit_playground.clear_tracked_pixels();
+    }
+
+    // Opens `port` with these settings and runs the Firmata handshake. The
+    // connection is watched so that `is_connected` notices when it fails.
+    // The firmata crate waits forever for the handshake replies, so the
+    // board is first probed for a reply within the timeout.
+    pub(crate) fn open(&self, port: &str) -> Result<(Board<Box<dyn Transport>>, Connected), CpError> {
+        let mut sp = serial::open(port)?;
+        let baud_rate = self.baud_rate;
+
+        sp.reconfigure(&|settings| {
+            settings.set_baud_rate(baud_rate)?;
+            settings.set_char_size(Bits8);
+            settings.set_parity(ParityNone);
+            settings.set_stop_bits(Stop1);
+            settings.set_flow_control(FlowNone);
+            Ok(())
+        })?;
+
+        // Reads only block briefly so that the reply timeout, which is checked
+        // between reads, is kept to and drain_input returns promptly.
+        sp.set_timeout(time::Duration::milliseconds(SERIAL_READ_TIMEOUT_MS))?;
+
+        let (mut connection, connected) = Watched::wrap(Box::new(sp));
+        probe_firmware(&mut connection, self.timeout)?;
+        let board = Board::new(Box::new(connection))?;
+
+        Ok((board, connected))
+    }
+}
+
+// Asks the board for its firmware name and version and waits for the reply,
+// returning `CpError::Timeout` if none arrives within `timeout`. Anything
+// else read on the way, such as the protocol version the firmware announces
+// on reset, is discarded.
+fn probe_firmware(connection: &mut dyn Transport, timeout: Duration) -> Result<(), CpError> {
+    connection.write_all(&[START_SYSEX, REPORT_FIRMWARE, END_SYSEX])?;
+    let deadline = Instant::now() + timeout;
+    // How far through the start of a firmware reply the last bytes were.
+    let mut matched = 0;
+
+    loop {
+        // Reading a byte at a time leaves whatever follows the reply for the
+        // handshake.
+        let mut byte = [0u8];
+        match connection.read(&mut byte) {
+            Ok(0) => {}
+            Ok(_) => match (matched, byte[0]) {
+                (2, END_SYSEX) => return Ok(()),
+                (2, _) => {}
+                (_, START_SYSEX) => matched = 1,
+                (1, REPORT_FIRMWARE) => matched = 2,
+                _ => matched = 0,
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut || crate::is_transient(e) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        if Instant::now() > deadline {
+            return Err(CpError::Timeout);
+        }
+        if matched == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_match_the_old_constructor() {
+        let builder = CircuitPlaygroundBuilder::new();
+
+        assert_eq!(builder.port, None);
+        assert_eq!(builder.baud_rate, Baud57600);
+        assert_eq!(builder.timeout, DEFAULT_REPLY_TIMEOUT);
+        assert_eq!(builder.pixel_count, DEFAULT_PIXEL_COUNT);
+        assert_eq!(builder.color_order, ColorOrder::Rgb);
+    }
+
+    #[test]
+    fn setters_override_the_defaults() {
+        let builder = CircuitPlaygroundBuilder::new()
+            .port("/dev/ttyACM0")
+            .baud_rate(serial::Baud115200)
+            .timeout(Duration::from_millis(250))
+            .pixel_count(30);
+
+        assert_eq!(builder.port.as_deref(), Some("/dev/ttyACM0"));
+        assert_eq!(builder.baud_rate, serial::Baud115200);
+        assert_eq!(builder.timeout, Duration::from_millis(250));
+        assert_eq!(builder.pixel_count, 30);
+    }
+
+    #[test]
+    fn pixel_count_is_kept_between_1_and_128() {
+        assert_eq!(CircuitPlaygroundBuilder::new().pixel_count(0).pixel_count, 1);
+        assert_eq!(CircuitPlaygroundBuilder::new().pixel_count(200).pixel_count, 128);
+    }
+
+    #[test]
+    fn probe_gives_up_on_a_board_that_never_answers() {
+        let mut mock = crate::MockTransport::new();
+        let started = Instant::now();
+
+        let result = probe_firmware(&mut mock, Duration::from_millis(50));
+        assert!(matches!(result, Err(CpError::Timeout)));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert_eq!(mock.written(), vec![START_SYSEX, REPORT_FIRMWARE, END_SYSEX]);
+    }
+
+    #[test]
+    fn probe_waits_for_the_firmware_reply() {
+        let mut mock = crate::MockTransport::new();
+        mock.queue_reply(&[0xF9, 2, 5]);
+        mock.queue_reply(&[START_SYSEX, REPORT_FIRMWARE, 2, 5, b'C', 0, END_SYSEX]);
+        mock.queue_reply(&[0xF9, 2, 5]);
+
+        probe_firmware(&mut mock, Duration::from_secs(1)).unwrap();
+        // The bytes after the reply are left for the handshake.
+        let mut rest = [0u8; 8];
+        assert_eq!(io::Read::read(&mut mock, &mut rest).unwrap(), 3);
+    }
+
+    #[test]
+    fn pixel_indexes_are_checked_against_the_configured_count() {
+        let mut cp = CircuitPlayground::with_transport(crate::MockTransport::new());
+        CircuitPlaygroundBuilder::new().pixel_count(30).configure(&mut cp);
+
+        assert_eq!(cp.pixel_count(), 30);
+        assert!(cp.set_pixel(29, (255, 0, 0)).is_ok());
+        assert!(matches!(cp.set_pixel(30, (255, 0, 0)), Err(CpError::InvalidPixel(30))));
+        assert!(cp.set_pixels(&[crate::Color::BLUE; 30]).is_ok());
+        assert!(matches!(cp.set_pixels(&[crate::Color::BLUE; 10]), Err(CpError::InvalidPixelCount { expected: 30, found: 10 })));
+    }
+}
@@ -0,0 +1,255 @@
+use std::sync::OnceLock;
+
+// Gamma used to build the NeoPixel correction table, the same curve as the
+// gamma8 table in Adafruit's NeoPixel library.
+const GAMMA: f64 = 2.8;
+
+/// A structure representing the red, green and blue channels of a NeoPixel color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const RED: Color =   Color { r: 255, g: 0,   b: 0   };
+    pub const GREEN: Color = Color { r: 0,   g: 255, b: 0   };
+    pub const BLUE: Color =  Color { r: 0,   g: 0,   b: 255 };
+    pub const WHITE: Color = Color { r: 255, g: 255, b: 255 };
+    pub const BLACK: Color = Color { r: 0,   g: 0,   b: 0   };
+
+    /// Creates a new `Color` from its red, green and blue channels.
+    pub fn new(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b }
+    }
+
+    /// Creates a `Color` from a hue in degrees (0.0-360.0) and a saturation
+    /// and value from 0.0 to 1.0. Hues outside that range wrap around the
+    /// color wheel, and saturation and value are clamped.
+    pub fn from_hsv(h: f32, s: f32, v: f32) -> Color {
+        let h = h.rem_euclid(360.0);
+        let s = s.clamp(0.0, 1.0);
+        let v = v.clamp(0.0, 1.0);
+
+        let chroma = v * s;
+        let x = chroma * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+        let m = v - chroma;
+
+        let (r, g, b) = match (h / 60.0) as u8 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+
+        Color::new(
+            ((r + m) * 255.0).round() as u8,
+            ((g + m) * 255.0).round() as u8,
+            ((b + m) * 255.0).round() as u8,
+        )
+    }
+
+    /// Maps `celsius` onto a gradient from blue at `min` through magenta to
+    /// red at `max`, for showing a temperature. Temperatures outside the range
+    /// are clamped to it.
+    pub fn from_temperature(celsius: f64, min: f64, max: f64) -> Color {
+        let t = if max > min {
+            crate::map_range_clamped(celsius, min, max, 0.0, 1.0)
+        } else if celsius >= max {
+            1.0
+        } else {
+            0.0
+        };
+
+        Color::new((t * 255.0).round() as u8, 0, ((1.0 - t) * 255.0).round() as u8)
+    }
+
+    /// Returns this color with gamma correction applied to each channel, so
+    /// that brightness steps look even to the eye. For example a channel
+    /// value of 128 is corrected down to 37.
+    pub fn gamma_correct(&self) -> Color {
+        let table = gamma_table();
+        Color::new(table[self.r as usize], table[self.g as usize], table[self.b as usize])
+    }
+
+    /// Returns this color with each channel multiplied by `factor`, which is
+    /// clamped to 0.0-1.0, so 0.5 gives the same color at half brightness.
+    pub fn scale(&self, factor: f32) -> Color {
+        let factor = factor.clamp(0.0, 1.0);
+        let channel = |value: u8| (value as f32 * factor).round() as u8;
+
+        Color::new(channel(self.r), channel(self.g), channel(self.b))
+    }
+
+    /// Returns the color `t` of the way from this color to `other`, mixing
+    /// each channel separately. `t` is clamped to 0.0-1.0, so 0.0 gives this
+    /// color, 1.0 gives `other` and 0.5 the average of the two.
+    pub fn blend(&self, other: Color, t: f32) -> Color {
+        let t = t.clamp(0.0, 1.0);
+        let channel = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+
+        Color::new(channel(self.r, other.r), channel(self.g, other.g), channel(self.b, other.b))
+    }
+
+    /// Packs the color into the four 7-bit Firmata bytes expected by
+    /// CP_PIXEL_SET, with the 24 bits of color in the upper bits.
+    pub fn to_firmata_bytes(&self) -> [u8; 4] {
+        self.to_firmata_bytes_in(ColorOrder::Rgb)
+    }
+
+    /// Like `to_firmata_bytes`, but sends the channels in `order`.
+    pub fn to_firmata_bytes_in(&self, order: ColorOrder) -> [u8; 4] {
+        let (first, second, third) = match order {
+            ColorOrder::Rgb => (self.r, self.g, self.b),
+            ColorOrder::Grb => (self.g, self.r, self.b),
+        };
+
+        [
+            (first >> 1) & 0x7F,
+            ((first & 0x01) << 6) | ((second >> 2) & 0x3F),
+            ((second & 0x03) << 5) | ((third >> 3) & 0x1F),
+            (third & 0x07) << 4,
+        ]
+    }
+}
+
+/// The order the color channels are sent to the board in. The Circuit
+/// Playground firmware takes red, green and blue and reorders them for its
+/// NeoPixels itself, so `Rgb` is right for it. Other boards running the same
+/// firmware with differently wired pixels may show red and green swapped,
+/// which `Grb` corrects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorOrder {
+    /// Red, green, blue, the default.
+    Rgb,
+    /// Green, red, blue.
+    Grb,
+}
+
+impl From<(u8, u8, u8)> for Color {
+    fn from((r, g, b): (u8, u8, u8)) -> Color {
+        Color::new(r, g, b)
+    }
+}
+
+/// Maps `pos` (0-255) onto a rainbow that goes from red to green to blue and
+/// back to red, like the `Wheel` function in Adafruit's NeoPixel examples.
+/// Position 0 is red, 85 is green, 170 is blue and 255 is red again.
+pub fn color_wheel(pos: u8) -> Color {
+    let pos = 255 - pos;
+
+    if pos < 85 {
+        Color::new(255 - pos * 3, 0, pos * 3)
+    } else if pos < 170 {
+        let pos = pos - 85;
+        Color::new(0, pos * 3, 255 - pos * 3)
+    } else {
+        let pos = pos - 170;
+        Color::new(pos * 3, 255 - pos * 3, 0)
+    }
+}
+
+/// Returns the 256 entry gamma correction table, building it on first use.
+pub fn gamma_table() -> &'static [u8; 256] {
+    static TABLE: OnceLock<[u8; 256]> = OnceLock::new();
+
+    TABLE.get_or_init(|| {
+        let mut table = [0u8; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            *entry = ((i as f64 / 255.0).powf(GAMMA) * 255.0 + 0.5) as u8;
+        }
+        table
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_colors_into_firmata_bytes() {
+        let cases = [
+            (Color::BLACK, [0, 0, 0, 0]),
+            (Color::RED, [127, 64, 0, 0]),
+            (Color::GREEN, [0, 63, 96, 0]),
+            (Color::BLUE, [0, 0, 31, 112]),
+            (Color::WHITE, [127, 127, 127, 112]),
+        ];
+
+        for &(color, bytes) in cases.iter() {
+            assert_eq!(color.to_firmata_bytes(), bytes, "{:?}", color);
+        }
+    }
+
+    #[test]
+    fn converts_from_tuples() {
+        assert_eq!(Color::from((1, 2, 3)), Color::new(1, 2, 3));
+    }
+
+    #[test]
+    fn converts_primary_hues_from_hsv() {
+        let cases = [
+            (0.0, (255, 0, 0)),
+            (60.0, (255, 255, 0)),
+            (120.0, (0, 255, 0)),
+            (180.0, (0, 255, 255)),
+            (240.0, (0, 0, 255)),
+            (300.0, (255, 0, 255)),
+        ];
+
+        for &(hue, (r, g, b)) in cases.iter() {
+            assert_eq!(Color::from_hsv(hue, 1.0, 1.0), Color::new(r, g, b), "hue {}", hue);
+        }
+    }
+
+    #[test]
+    fn hsv_wraps_hues_and_clamps_saturation_and_value() {
+        assert_eq!(Color::from_hsv(360.0, 1.0, 1.0), Color::RED);
+        assert_eq!(Color::from_hsv(-120.0, 1.0, 1.0), Color::BLUE);
+        assert_eq!(Color::from_hsv(0.0, 0.0, 2.0), Color::WHITE);
+        assert_eq!(Color::from_hsv(0.0, 1.0, -1.0), Color::BLACK);
+    }
+
+    #[test]
+    fn color_wheel_goes_red_green_blue_red() {
+        assert_eq!(color_wheel(0), Color::RED);
+        assert_eq!(color_wheel(85), Color::GREEN);
+        assert_eq!(color_wheel(170), Color::BLUE);
+        assert_eq!(color_wheel(255), Color::RED);
+    }
+
+    #[test]
+    fn gamma_correct_darkens_midtones() {
+        assert_eq!(Color::new(128, 128, 128).gamma_correct(), Color::new(37, 37, 37));
+        assert_eq!(Color::BLACK.gamma_correct(), Color::BLACK);
+        assert_eq!(Color::WHITE.gamma_correct(), Color::WHITE);
+    }
+
+    #[test]
+    fn gamma_table_never_decreases() {
+        let table = gamma_table();
+        assert!(table.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn temperatures_map_from_blue_to_red() {
+        assert_eq!(Color::from_temperature(20.0, 20.0, 30.0), Color::BLUE);
+        assert_eq!(Color::from_temperature(25.0, 20.0, 30.0), Color::new(128, 0, 128));
+        assert_eq!(Color::from_temperature(30.0, 20.0, 30.0), Color::RED);
+
+        assert_eq!(Color::from_temperature(-5.0, 20.0, 30.0), Color::BLUE);
+        assert_eq!(Color::from_temperature(45.0, 20.0, 30.0), Color::RED);
+    }
+
+    #[test]
+    fn grb_order_swaps_red_and_green() {
+        let color = Color::new(0x12, 0xAB, 0x5C);
+
+        assert_eq!(color.to_firmata_bytes_in(ColorOrder::Rgb), color.to_firmata_bytes());
+        assert_eq!(color.to_firmata_bytes_in(ColorOrder::Grb), Color::new(0xAB, 0x12, 0x5C).to_firmata_bytes());
+        assert_eq!(Color::RED.to_firmata_bytes_in(ColorOrder::Grb), Color::GREEN.to_firmata_bytes());
+    }
+}
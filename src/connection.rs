@@ -0,0 +1,57 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::Transport;
+
+// The flag a `Watched` transport clears when it fails.
+pub(crate) type Connected = Arc<AtomicBool>;
+
+// Wraps the transport a board is connected over and notes when reading or
+// writing fails with anything other than a timeout or a transient error,
+// which for a serial port almost always means the board was unplugged. The
+// firmata crate talks to the transport directly as well, so this catches its
+// failures too.
+pub(crate) struct Watched {
+    inner: Box<dyn Transport>,
+    connected: Connected,
+}
+
+impl Watched {
+    // Wraps `inner`, returning it along with the flag that is cleared when
+    // it fails.
+    pub(crate) fn wrap(inner: Box<dyn Transport>) -> (Box<dyn Transport>, Connected) {
+        let connected = Arc::new(AtomicBool::new(true));
+        let watched = Watched { inner, connected: connected.clone() };
+
+        (Box::new(watched), connected)
+    }
+
+    fn check<T>(&self, result: io::Result<T>) -> io::Result<T> {
+        if let Err(ref e) = result {
+            if e.kind() != io::ErrorKind::TimedOut && !crate::is_transient(e) {
+                self.connected.store(false, Ordering::Relaxed);
+            }
+        }
+        result
+    }
+}
+
+impl io::Read for Watched {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.inner.read(buf);
+        self.check(result)
+    }
+}
+
+impl io::Write for Watched {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.inner.write(buf);
+        self.check(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.inner.flush();
+        self.check(result)
+    }
+}
@@ -0,0 +1,60 @@
+/// How an animation moves between its start and end, as taken by
+/// `apply_easing`. The eased curves other than `Sine` are cubic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    /// Moves at a constant rate.
+    Linear,
+    /// Starts slowly and speeds up.
+    EaseIn,
+    /// Starts quickly and slows down.
+    EaseOut,
+    /// Starts and ends slowly, moving fastest halfway through.
+    EaseInOut,
+    /// Follows half a cosine wave, which is gentler than `EaseInOut` at the
+    /// ends. This is the breathing curve, and the one to use with `pulse`.
+    Sine,
+}
+
+/// Maps how far through an animation it is, `t`, from 0.0 to 1.0, to how far
+/// it should have moved along the `easing` curve, also from 0.0 to 1.0.
+/// Values of `t` outside 0.0-1.0 are clamped.
+pub fn apply_easing(t: f32, easing: Easing) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+
+    match easing {
+        Easing::Linear => t,
+        Easing::EaseIn => t * t * t,
+        Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+        Easing::EaseInOut if t < 0.5 => 4.0 * t * t * t,
+        Easing::EaseInOut => 1.0 - (2.0 - 2.0 * t).powi(3) / 2.0,
+        Easing::Sine => 0.5 - 0.5 * (std::f32::consts::PI * t).cos(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curves_start_at_0_and_end_at_1() {
+        for &easing in [Easing::Linear, Easing::EaseIn, Easing::EaseOut, Easing::EaseInOut, Easing::Sine].iter() {
+            assert_eq!(apply_easing(0.0, easing), 0.0, "{:?}", easing);
+            assert_eq!(apply_easing(1.0, easing), 1.0, "{:?}", easing);
+            assert_eq!(apply_easing(-0.5, easing), 0.0, "{:?}", easing);
+            assert_eq!(apply_easing(1.5, easing), 1.0, "{:?}", easing);
+        }
+    }
+
+    #[test]
+    fn ease_in_out_and_sine_are_slow_at_the_ends() {
+        assert_eq!(apply_easing(0.5, Easing::EaseInOut), 0.5);
+        assert_eq!(apply_easing(0.25, Easing::EaseInOut), 0.0625);
+        assert_eq!(apply_easing(0.75, Easing::EaseInOut), 0.9375);
+
+        assert!((apply_easing(0.5, Easing::Sine) - 0.5).abs() < 1e-6);
+        assert!((apply_easing(0.25, Easing::Sine) - 0.1464).abs() < 1e-4);
+
+        assert!(apply_easing(0.25, Easing::EaseIn) < 0.25);
+        assert!(apply_easing(0.25, Easing::EaseOut) > 0.25);
+    }
+}
@@ -0,0 +1,127 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::{CAP_PINS, PIXEL_ANGLES, Version};
+
+/// The errors that can be returned when talking to a Circuit Playground.
+#[derive(Debug)]
+pub enum CpError {
+    /// The serial port could not be opened or configured.
+    Serial(serial::Error),
+    /// Reading from or writing to the board failed.
+    Io(io::Error),
+    /// The board didn't reply in time.
+    Timeout,
+    /// A reply with this sub-command was too short to decode.
+    MalformedReply(u8),
+    /// A NeoPixel index was out of range.
+    InvalidPixel(u8),
+    /// A pin was passed that isn't a capacitive touch input.
+    InvalidCapPin(u8),
+    /// A pin was passed that the board didn't report having.
+    InvalidPin(u8),
+    /// A brightness above 100 was passed to `Brightness::new`.
+    InvalidBrightness(u8),
+    /// A tone frequency above 16383 hz was passed.
+    InvalidFrequency(u16),
+    /// A tone duration above 16383 ms was passed.
+    InvalidDuration(u16),
+    /// A slice with the wrong number of pixel colors was passed.
+    InvalidPixelCount { expected: usize, found: usize },
+    /// The board wasn't connected through a serial port, so it can't be
+    /// reconnected.
+    NoPort,
+    /// No Circuit Playground was found among the listed serial ports.
+    NoBoardFound(Vec<String>),
+    /// The firmware is older than the `(major, minor)` version required.
+    VersionTooOld { required: (u8, u8), found: Version },
+    /// The firmware has no commands for this feature of the board.
+    Unsupported(&'static str),
+    /// A helper that follows the Circuit Playground's ring of ten NeoPixels
+    /// was used on a board set up with this many pixels instead.
+    NotARing(u8),
+}
+
+impl fmt::Display for CpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CpError::Serial(ref e) => write!(f, "serial port error: {}", e),
+            CpError::Io(ref e) => write!(f, "i/o error: {}", e),
+            CpError::Timeout => write!(f, "timed out waiting for a reply from the board"),
+            CpError::MalformedReply(sub) => write!(f, "reply 0x{:02X} from the board was too short", sub),
+            CpError::InvalidPixel(index) => write!(f, "pixel index {} is out of range", index),
+            CpError::InvalidCapPin(pin) =>
+                write!(f, "pin {} is not a cap touch input, expected one of {:?}", pin, CAP_PINS),
+            CpError::InvalidPin(pin) => write!(f, "pin {} is not on the board", pin),
+            CpError::InvalidBrightness(level) => write!(f, "brightness {} is out of range, expected 0-100", level),
+            CpError::InvalidFrequency(hz) => write!(f, "tone frequency {} hz is above the maximum of 16383 hz", hz),
+            CpError::InvalidDuration(ms) => write!(f, "tone duration {} ms is above the maximum of 16383 ms", ms),
+            CpError::InvalidPixelCount { expected, found } =>
+                write!(f, "expected exactly {} pixel colors, got {}", expected, found),
+            CpError::NoPort => write!(f, "the board has no serial port to reconnect to"),
+            CpError::NoBoardFound(ref ports) if ports.is_empty() => write!(f, "no serial ports were found"),
+            CpError::NoBoardFound(ref ports) =>
+                write!(f, "no Circuit Playground found, checked: {}", ports.join(", ")),
+            CpError::VersionTooOld { required: (major, minor), found } =>
+                write!(f, "firmware version {} is older than the required {}.{}", found, major, minor),
+            CpError::Unsupported(feature) => write!(f, "the firmware doesn't support {}", feature),
+            CpError::NotARing(count) =>
+                write!(f, "this follows the ring of {} pixels, but the board has {}", PIXEL_ANGLES.len(), count),
+        }
+    }
+}
+
+impl Error for CpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            CpError::Serial(ref e) => Some(e),
+            CpError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CpError {
+    fn from(error: io::Error) -> CpError {
+        if error.kind() == io::ErrorKind::TimedOut {
+            CpError::Timeout
+        } else {
+            CpError::Io(error)
+        }
+    }
+}
+
+impl From<serial::Error> for CpError {
+    fn from(error: serial::Error) -> CpError {
+        CpError::Serial(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_timeouts_become_timeout_errors() {
+        assert!(matches!(CpError::from(io::Error::from(io::ErrorKind::TimedOut)), CpError::Timeout));
+
+        let err = CpError::from(io::Error::from(io::ErrorKind::BrokenPipe));
+        assert!(matches!(err, CpError::Io(_)));
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn messages_name_the_bad_value() {
+        assert_eq!(CpError::InvalidPixel(12).to_string(), "pixel index 12 is out of range");
+        assert_eq!(
+            CpError::InvalidCapPin(4).to_string(),
+            "pin 4 is not a cap touch input, expected one of [0, 1, 2, 3, 6, 9, 10, 12]"
+        );
+        assert_eq!(CpError::NoBoardFound(vec![]).to_string(), "no serial ports were found");
+        assert_eq!(
+            CpError::NoBoardFound(vec!["COM1".to_string(), "COM2".to_string()]).to_string(),
+            "no Circuit Playground found, checked: COM1, COM2"
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+use crate::TapState;
+
+/// A run of taps recognized by a `TapGestureDetector`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapGesture {
+    Single,
+    Double,
+    Triple,
+}
+
+/// Counts taps from the tap stream and turns runs of them into
+/// `Single`, `Double` and `Triple` gestures. A run ends once no tap has
+/// arrived for the length of the window, or as soon as it reaches three taps.
+#[derive(Debug, Clone)]
+pub struct TapGestureDetector {
+    window: Duration,
+    count: u32,
+    last_tap: Option<Instant>,
+}
+
+impl TapGestureDetector {
+    /// Creates a new `TapGestureDetector` that counts taps less than
+    /// `window` apart as part of the same gesture.
+    pub fn new(window: Duration) -> TapGestureDetector {
+        TapGestureDetector {
+            window,
+            count: 0,
+            last_tap: None,
+        }
+    }
+
+    /// Feeds one tap state from the stream in, read at `now`, returning a
+    /// gesture if one was completed. A double tap reported by the
+    /// accelerometer counts as two taps.
+    pub fn update(&mut self, state: TapState, now: Instant) -> Option<TapGesture> {
+        let taps = if state.double { 2 } else if state.single { 1 } else { 0 };
+
+        if taps == 0 {
+            return self.check(now);
+        }
+
+        // Taps that come after the window closed start a new gesture, but
+        // the one they ended still has to be reported first.
+        let finished = self.check(now);
+
+        self.count += taps;
+        self.last_tap = Some(now);
+
+        if finished.is_some() {
+            return finished;
+        }
+        if self.count >= 3 {
+            self.reset();
+            return Some(TapGesture::Triple);
+        }
+        None
+    }
+
+    /// Returns the gesture made by the taps counted so far if the window
+    /// has closed at `now`. Use this to finish a gesture when no more tap
+    /// states are coming.
+    pub fn check(&mut self, now: Instant) -> Option<TapGesture> {
+        let last_tap = self.last_tap?;
+
+        if now.duration_since(last_tap) <= self.window {
+            return None;
+        }
+
+        let gesture = match self.count {
+            1 => TapGesture::Single,
+            2 => TapGesture::Double,
+            _ => TapGesture::Triple,
+        };
+        self.reset();
+        Some(gesture)
+    }
+
+    fn reset(&mut self) {
+        self.count = 0;
+        self.last_tap = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SINGLE: TapState = TapState { single: true, double: false };
+    const DOUBLE: TapState = TapState { single: true, double: true };
+    const NO_TAP: TapState = TapState { single: false, double: false };
+
+    // Feeds `taps`, each with its time in milliseconds, into a detector with
+    // a 300 ms window, returning the gestures it recognized.
+    fn gestures(taps: &[(TapState, u64)]) -> Vec<TapGesture> {
+        let start = Instant::now();
+        let mut detector = TapGestureDetector::new(Duration::from_millis(300));
+
+        taps.iter()
+            .filter_map(|&(state, ms)| detector.update(state, start + Duration::from_millis(ms)))
+            .collect()
+    }
+
+    #[test]
+    fn recognizes_runs_of_taps() {
+        assert_eq!(gestures(&[(SINGLE, 0), (NO_TAP, 400)]), vec![TapGesture::Single]);
+        assert_eq!(gestures(&[(SINGLE, 0), (SINGLE, 200), (NO_TAP, 600)]), vec![TapGesture::Double]);
+        assert_eq!(gestures(&[(SINGLE, 0), (SINGLE, 200), (SINGLE, 400)]), vec![TapGesture::Triple]);
+        assert_eq!(gestures(&[(DOUBLE, 0), (SINGLE, 100)]), vec![TapGesture::Triple]);
+    }
+
+    #[test]
+    fn a_tap_after_the_window_starts_a_new_gesture() {
+        let taps = [(SINGLE, 0), (SINGLE, 500), (SINGLE, 600), (NO_TAP, 1000)];
+        assert_eq!(gestures(&taps), vec![TapGesture::Single, TapGesture::Double]);
+    }
+
+    #[test]
+    fn nothing_is_reported_while_the_window_is_open() {
+        assert!(gestures(&[(SINGLE, 0), (NO_TAP, 100), (NO_TAP, 300)]).is_empty());
+    }
+}
@@ -34,108 +34,4312 @@
 // SOFTWARE.
 
 
+#[cfg(feature = "async")]
+mod asynchronous;
+mod batch;
+mod builder;
+mod color;
+mod connection;
+mod easing;
+mod error;
+mod gesture;
+mod mock;
+mod note;
+mod ports;
+pub mod protocol;
+mod smooth;
+mod stream;
+mod touch;
+
+#[cfg(feature = "async")]
+pub use asynchronous::{AsyncCircuitPlayground, Pending};
+pub use batch::Batch;
+pub use builder::CircuitPlaygroundBuilder;
+pub use color::{Color, ColorOrder, color_wheel, gamma_table};
+pub use easing::{Easing, apply_easing};
+pub use error::CpError;
+pub use gesture::{TapGesture, TapGestureDetector};
+pub use mock::MockTransport;
+pub use note::{Note, NoteValue};
+pub use serial::BaudRate;
+pub use ports::{ADAFRUIT_VID, CIRCUIT_PLAYGROUND_PIDS, PortInfo, find_circuit_playground, list_ports};
+pub use smooth::LightSmoother;
+pub use stream::{EventStream, SensorStream, StreamGuard, TemperatureMonitor};
+pub use touch::{TouchDebouncer, TouchEvent};
+
 use firmata::*;
-use hex::*;
-use serial-unix;
-use serial-windows;
-use std::env;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use note::ScheduledNote;
 
 // Constants that define the Circuit Playground Firmata command values.
-static CP_COMMAND: &str =               "0x40";     // Byte that identifies all Circuit Playground commands.
-static CP_PIXEL_SET: &str =             "0x10";     // Set NeoPixel, expects the following bytes as data:
-                                                    //  - Pixel ID (0-9)
-                                                    //  - Pixel RGB color data as 4 7-bit bytes.  The upper
-                                                    //    24 bits will be mapped to the R, G, B bytes.
-static CP_PIXEL_SHOW: &str =            "0x11";     // Update NeoPixels with their current color values.
-static CP_PIXEL_CLEAR: &str =           "0x12";     // Clear all NeoPixels to black/off.  Must call show pixels after this to see the change!
-static CP_PIXEL_BRIGHTNESS: &str =      "0x13";     // Set the brightness of the NeoPixels, just like calling the
-                                                    // NeoPixel library setBrightness function.  Takes one parameter
-                                                    // which is a single byte with a value 0-100.
-static CP_TONE: &str =                  "0x20";     // Play a tone on the speaker, expects the following bytes as data:
-                                                    //  - Frequency (hz) as 2 7-bit bytes (up to 2^14 hz, or about 16khz)
-                                                    //  - Duration (ms) as 2 7-bit bytes.
-static CP_NO_TONE: &str =               "0x21";     // Stop playing anything on the speaker.
-static CP_ACCEL_READ: &str =            "0x30";     // Return the current x, y, z accelerometer values.
-static CP_ACCEL_TAP: &str =             "0x31";     // Return the current accelerometer tap state.
-static CP_ACCEL_READ_REPLY: &str =      "0x36";     // Result of an accelerometer read.  Includes 3 floating point values (4 bytes each) with x, y, z
-                                                    // acceleration in meters/second^2.
-static CP_ACCEL_TAP_REPLY: &str =       "0x37";     // Result of the tap sensor read.  Includes a byte with the tap register value.
-static CP_ACCEL_TAP_STREAM_ON: &str =   "0x38";     // Turn on continuous streaming of tap data.
-static CP_ACCEL_TAP_STREAM_OFF: &str =  "0x39";     // Turn off streaming of tap data.
-static CP_ACCEL_STREAM_ON: &str =       "0x3A";     // Turn on continuous streaming of accelerometer data.
-static CP_ACCEL_STREAM_OFF: &str =      "0x3B";     // Turn off streaming of accelerometer data.
-static CP_ACCEL_RANGE: &str =           "0x3C";     // Set the range of the accelerometer, takes one byte as a parameter.
-                                                    // Use a value 0=+/-2G, 1=+/-4G, 2=+/-8G, 3=+/-16G
-static CP_ACCEL_TAP_CONFIG: &str =      "0x3D";     // Set the sensitivity of the tap detection, takes 4 bytes of 7-bit firmata
-                                                    // data as parameters which expand to 2 unsigned 8-bit bytes value to set:
-                                                    //   - Type of click: 0 = no click detection, 1 = single click, 2 = single & double click (default)
-                                                    //   - Click threshold: 0-255, the higher the value the less sensitive.  Depends on the accelerometer
-                                                    //     range, good values are: +/-16G = 5-10, +/-8G = 10-20, +/-4G = 20-40, +/-2G = 40-80
-                                                    //     80 is the default value (goes well with default of +/-2G)
-static CP_CAP_READ: &str =              "0x40";     // Read a single capacitive input.  Expects a byte as a parameter with the
-                                                    // cap touch input to read (0, 1, 2, 3, 6, 9, 10, 12).  Will respond with a
-                                                    // CP_CAP_REPLY message.
-static CP_CAP_ON: &str =                "0x41";     // Turn on continuous cap touch reads for the specified input (sent as a byte parameter).
-static CP_CAP_OFF: &str =               "0x42";     // Turn off continuous cap touch reads for the specified input (sent as a byte parameter).
-static CP_CAP_REPLY: &str =             "0x43";     // Capacitive input read response.  Includes a byte with the pin # of the cap input, then
-                                                    // four bytes of data which represent an int32_t value read from the cap input.
-static CP_SENSECOLOR: &str =            "0x50";     // Perform a color sense using the NeoPixel and light sensor.
-static CP_SENSECOLOR_REPLY: &str =      "0x51";     // Result of a color sense, will return the red, green, blue color
-                                                    // values that were read from the light sensor.  This will return
-                                                    // 6 bytes of data:
-                                                    //  - red color (unsigned 8 bit value, split across 2 7-bit bytes)
-                                                    //  - green color (unsigned 8 bit value, split across 2 7-bit bytes)
-                                                    //  - blue color (unsigned 8 bit value, split across 2 7-bit bytes)
-static CP_IMPL_VERS: &str =             "0x60";     // Get the implementation version, 3 bytes of Major, Minor, Bugfix
-static CP_IMPL_VERS_REPLY: &str =       "0x61";
-
-
-// Accelerometer constants to be passed to set_accel_range.
-static ACCEL_2G: u8  = 0;
-static ACCEL_4G: u8  = 1;
-static ACCEL_8G: u8  = 2;
-static ACCEL_16G: u8 = 3;
+//
+// CP_COMMAND and CP_CAP_READ are both 0x40. This matches the firmware and is
+// not a clash: CP_COMMAND is the sysex command byte that starts every message,
+// while CP_CAP_READ and the rest are sub-commands sent in the byte after it.
+// Only the sub-commands need to be distinct from each other.
+pub const CP_COMMAND: u8 =                  0x40;       // Byte that identifies all Circuit Playground commands.
+pub const CP_PIXEL_SET: u8 =                0x10;       // Set NeoPixel, expects the following bytes as data:
+                                                        //  - Pixel ID (0-9)
+                                                        //  - Pixel RGB color data as 4 7-bit bytes.  The upper
+                                                        //    24 bits will be mapped to the R, G, B bytes.
+pub const CP_PIXEL_SHOW: u8 =               0x11;       // Update NeoPixels with their current color values.
+pub const CP_PIXEL_CLEAR: u8 =              0x12;       // Clear all NeoPixels to black/off.  Must call show pixels after this to see the change!
+pub const CP_PIXEL_BRIGHTNESS: u8 =         0x13;       // Set the brightness of the NeoPixels, just like calling the
+                                                        // NeoPixel library setBrightness function.  Takes one parameter
+                                                        // which is a single byte with a value 0-100.
+pub const CP_TONE: u8 =                     0x20;       // Play a tone on the speaker, expects the following bytes as data:
+                                                        //  - Frequency (hz) as 2 7-bit bytes (up to 2^14 hz, or about 16khz)
+                                                        //  - Duration (ms) as 2 7-bit bytes.
+pub const CP_NO_TONE: u8 =                  0x21;       // Stop playing anything on the speaker.
+pub const CP_ACCEL_READ: u8 =               0x30;       // Return the current x, y, z accelerometer values.
+pub const CP_ACCEL_TAP: u8 =                0x31;       // Return the current accelerometer tap state.
+pub const CP_ACCEL_READ_REPLY: u8 =         0x36;       // Result of an accelerometer read.  Includes 3 floating point values (4 bytes each) with x, y, z
+                                                        // acceleration in meters/second^2.
+pub const CP_ACCEL_TAP_REPLY: u8 =          0x37;       // Result of the tap sensor read.  Includes a byte with the tap register value.
+pub const CP_ACCEL_TAP_STREAM_ON: u8 =      0x38;       // Turn on continuous streaming of tap data.
+pub const CP_ACCEL_TAP_STREAM_OFF: u8 =     0x39;       // Turn off streaming of tap data.
+pub const CP_ACCEL_STREAM_ON: u8 =          0x3A;       // Turn on continuous streaming of accelerometer data.
+pub const CP_ACCEL_STREAM_OFF: u8 =         0x3B;       // Turn off streaming of accelerometer data.
+pub const CP_ACCEL_RANGE: u8 =              0x3C;       // Set the range of the accelerometer, takes one byte as a parameter.
+                                                        // Use a value 0=+/-2G, 1=+/-4G, 2=+/-8G, 3=+/-16G
+pub const CP_ACCEL_TAP_CONFIG: u8 =         0x3D;       // Set the sensitivity of the tap detection, takes 4 bytes of 7-bit firmata
+                                                        // data as parameters which expand to 2 unsigned 8-bit bytes value to set:
+                                                        //   - Type of click: 0 = no click detection, 1 = single click, 2 = single & double click (default)
+                                                        //   - Click threshold: 0-255, the higher the value the less sensitive.  Depends on the accelerometer
+                                                        //     range, good values are: +/-16G = 5-10, +/-8G = 10-20, +/-4G = 20-40, +/-2G = 40-80
+                                                        //     80 is the default value (goes well with default of +/-2G)
+pub const CP_CAP_READ: u8 =                 0x40;       // Read a single capacitive input.  Expects a byte as a parameter with the
+                                                        // cap touch input to read (0, 1, 2, 3, 6, 9, 10, 12).  Will respond with a
+                                                        // CP_CAP_REPLY message.
+pub const CP_CAP_ON: u8 =                   0x41;       // Turn on continuous cap touch reads for the specified input (sent as a byte parameter).
+pub const CP_CAP_OFF: u8 =                  0x42;       // Turn off continuous cap touch reads for the specified input (sent as a byte parameter).
+pub const CP_CAP_REPLY: u8 =                0x43;       // Capacitive input read response.  Includes a byte with the pin # of the cap input, then
+                                                        // four bytes of data which represent an int32_t value read from the cap input.
+pub const CP_SENSECOLOR: u8 =               0x50;       // Perform a color sense using the NeoPixel and light sensor.
+pub const CP_SENSECOLOR_REPLY: u8 =         0x51;       // Result of a color sense, will return the red, green, blue color
+                                                        // values that were read from the light sensor.  This will return
+                                                        // 6 bytes of data:
+                                                        //  - red color (unsigned 8 bit value, split across 2 7-bit bytes)
+                                                        //  - green color (unsigned 8 bit value, split across 2 7-bit bytes)
+                                                        //  - blue color (unsigned 8 bit value, split across 2 7-bit bytes)
+pub const CP_IMPL_VERS: u8 =                0x60;       // Get the implementation version, 3 bytes of Major, Minor, Bugfix
+pub const CP_IMPL_VERS_REPLY: u8 =          0x61;
+
+
+// Fails to compile if two of the sub-commands above share a value.
+const _: () = {
+    let commands = [
+        CP_PIXEL_SET, CP_PIXEL_SHOW, CP_PIXEL_CLEAR, CP_PIXEL_BRIGHTNESS, CP_TONE, CP_NO_TONE,
+        CP_ACCEL_READ, CP_ACCEL_TAP, CP_ACCEL_READ_REPLY, CP_ACCEL_TAP_REPLY,
+        CP_ACCEL_TAP_STREAM_ON, CP_ACCEL_TAP_STREAM_OFF, CP_ACCEL_STREAM_ON, CP_ACCEL_STREAM_OFF,
+        CP_ACCEL_RANGE, CP_ACCEL_TAP_CONFIG, CP_CAP_READ, CP_CAP_ON, CP_CAP_OFF, CP_CAP_REPLY,
+        CP_SENSECOLOR, CP_SENSECOLOR_REPLY, CP_IMPL_VERS, CP_IMPL_VERS_REPLY,
+    ];
+    let mut i = 0;
+    while i < commands.len() {
+        let mut j = i + 1;
+        while j < commands.len() {
+            assert!(commands[i] != commands[j], "duplicate Circuit Playground sub-command");
+            j += 1;
+        }
+        i += 1;
+    }
+};
 
 // Constants for some of the board peripherals
-static THERM_PIN: u8 =              0;              // Analog input connected to the thermistor.
-static THERM_SERIES_OHMS: f64 =     10000.0;        // Resistor value in series with thermistor.
-static THERM_NOMINAL_OHMS: f64 =    10000.0;        // Thermistor resistance at 25 degrees C.
-static THERM_NOMIMAL_C: f64 =       25.0;           // Thermistor temperature at nominal resistance.
-static THERM_BETA: f64 =            3950.0;         // Thermistor beta coefficient.
-static CAP_THRESHOLD: u64 =         300;            // Threshold for considering a cap touch input pressed.
-                                                    // If the cap touch value is above this value it is
-                                                    // considered touched.
+pub const THERM_PIN: u8 =               0;              // Analog input connected to the thermistor.
+pub const THERM_SERIES_OHMS: f64 =      10000.0;        // Resistor value in series with thermistor.
+pub const THERM_NOMINAL_OHMS: f64 =     10000.0;        // Thermistor resistance at 25 degrees C.
+pub const THERM_NOMINAL_C: f64 =        25.0;           // Thermistor temperature at nominal resistance.
+pub const THERM_BETA: f64 =             3950.0;         // Thermistor beta coefficient.
+pub const LIGHT_PIN: u8 =               5;              // Analog input connected to the light sensor.
+pub const SOUND_PIN: u8 =               4;              // Analog input connected to the microphone.
+pub const BUTTON_A_PIN: u8 =            4;              // Digital input connected to the left button (A).
+pub const BUTTON_B_PIN: u8 =            19;             // Digital input connected to the right button (B).
+pub const SLIDE_SWITCH_PIN: u8 =        21;             // Digital input connected to the slide switch.
+pub const RED_LED_PIN: u8 =             13;             // Digital output connected to the red LED.
+pub const CAP_PINS: [u8; 8] =           [0, 1, 2, 3, 6, 9, 10, 12]; // Inputs that support capacitive touch.
+pub const CAP_THRESHOLD: u64 =          300;            // Threshold for considering a cap touch input pressed.
+                                                        // If the cap touch value is above this value it is
+                                                        // considered touched.
+
+/// The NeoPixel nearest each capacitive touch pad, as `(pad, pixel)` pairs.
+/// With the USB port at the top, pads 3, 2, 0 and 1 run down the left edge
+/// beside pixels 0 to 4, and pads 12, 6, 9 and 10 down the right edge beside
+/// pixels 9 to 5. Pads sitting between two pixels take the one nearer the
+/// top of the board.
+pub const CAP_PAD_PIXELS: [(u8, u8); 8] = [(3, 0), (2, 1), (0, 3), (1, 4), (10, 5), (9, 6), (6, 8), (12, 9)];
+
+/// The ranges that can be passed to `set_accel_range`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccelRange {
+    /// +/-2G, the default.
+    G2,
+    /// +/-4G.
+    G4,
+    /// +/-8G.
+    G8,
+    /// +/-16G.
+    G16,
+}
+
+impl AccelRange {
+    /// Returns the byte sent to the board for this range.
+    pub fn to_byte(&self) -> u8 {
+        match *self {
+            AccelRange::G2  => 0,
+            AccelRange::G4  => 1,
+            AccelRange::G8  => 2,
+            AccelRange::G16 => 3,
+        }
+    }
+}
+
+/// The kinds of tap that the accelerometer can be set to detect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClickType {
+    /// No tap detection.
+    None,
+    /// Single taps only.
+    Single,
+    /// Single and double taps, the default.
+    SingleDouble,
+}
+
+impl ClickType {
+    /// Returns the byte sent to the board for this click type.
+    pub fn to_byte(&self) -> u8 {
+        match *self {
+            ClickType::None         => 0,
+            ClickType::Single       => 1,
+            ClickType::SingleDouble => 2,
+        }
+    }
+}
+
+/// How `sweep_tone` steps from its start frequency to its end frequency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SweepCurve {
+    /// Steps of the same size in hz.
+    Linear,
+    /// Steps of the same ratio, which sound evenly spaced in pitch.
+    Logarithmic,
+}
+
+/// A structure representing the tap state reported by the accelerometer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TapState {
+    pub single: bool,
+    pub double: bool,
+}
+
+impl TapState {
+    /// Decodes the accelerometer's click source register.
+    pub fn from_register(register: u8) -> TapState {
+        TapState {
+            single: register & 0x10 != 0,
+            double: register & 0x20 != 0,
+        }
+    }
+}
+
+/// Which way the board is facing or tilted, as returned by `read_orientation`.
+///
+/// These follow the accelerometer axes: `TiltLeft` and `TiltRight` mean gravity
+/// is mostly along the positive or negative x axis, `TiltForward` and
+/// `TiltBack` along the positive or negative y axis, and `FaceUp` and
+/// `FaceDown` along the positive or negative z axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    FaceUp,
+    FaceDown,
+    TiltLeft,
+    TiltRight,
+    TiltForward,
+    TiltBack,
+}
+
+impl Orientation {
+    /// Classifies an acceleration vector in meters/second^2 by the axis that
+    /// gravity dominates. Returns `None` if no axis is above `threshold`,
+    /// which happens when the board sits between two orientations.
+    pub fn from_acceleration(x: f32, y: f32, z: f32, threshold: f32) -> Option<Orientation> {
+        let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+
+        if az >= ax && az >= ay && az > threshold {
+            Some(if z > 0.0 { Orientation::FaceUp } else { Orientation::FaceDown })
+        } else if ax >= ay && ax > threshold {
+            Some(if x > 0.0 { Orientation::TiltLeft } else { Orientation::TiltRight })
+        } else if ay > threshold {
+            Some(if y > 0.0 { Orientation::TiltForward } else { Orientation::TiltBack })
+        } else {
+            None
+        }
+    }
+}
+
+/// Computes the pitch and roll in degrees from an acceleration vector, with
+/// both being 0 when the board lies flat and face up.
+///
+/// Pitch is the rotation around the y axis: it is positive when the positive
+/// x edge of the board is lowered. Roll is the rotation around the x axis: it
+/// is positive when the positive y edge of the board is raised.
+pub fn pitch_roll(x: f32, y: f32, z: f32) -> (f32, f32) {
+    let pitch = (-x).atan2((y * y + z * z).sqrt()).to_degrees();
+    let roll = y.atan2(z).to_degrees();
+    (pitch, roll)
+}
+
+// Where each NeoPixel sits around the ring, in degrees clockwise from the
+// USB port. The ring has gaps at the USB port and the battery connector, so
+// pixels 0-4 run down the left side and 5-9 back up the right.
+const PIXEL_ANGLES: [f32; 10] = [330.0, 300.0, 270.0, 240.0, 210.0, 150.0, 120.0, 90.0, 60.0, 30.0];
+
+// Sideways acceleration in meters/second^2 below which bubble_level treats
+// the board as level, about 3 degrees of tilt.
+const LEVEL_THRESHOLD: f32 = 0.5;
+
+/// Returns the NeoPixel closest to `angle`, in degrees clockwise from the USB
+/// port.
+pub fn pixel_at_angle(angle: f32) -> u8 {
+    let angle = angle.rem_euclid(360.0);
+    let distance = |pixel: f32| {
+        let difference = (pixel - angle).abs();
+        difference.min(360.0 - difference)
+    };
+
+    let mut closest = 0;
+    for (index, &pixel) in PIXEL_ANGLES.iter().enumerate() {
+        if distance(pixel) < distance(PIXEL_ANGLES[closest]) {
+            closest = index;
+        }
+    }
+    closest as u8
+}
+
+/// Sideways acceleration in meters/second^2 below which `tilt_arrow` treats
+/// the board as flat and lights nothing, unless changed with
+/// `set_tilt_dead_zone`.
+pub const TILT_DEAD_ZONE: f32 = 1.0;
+
+// How far either side of the arrow's direction, in degrees, tilt_arrow
+// lights the ring. Pixels further round, towards the opposite side, are off.
+const TILT_ARROW_SPREAD: f32 = 90.0;
+
+/// Returns the NeoPixel on the lowest edge of the board for an acceleration
+/// vector in meters/second^2, or `None` if the board is close to level. The
+/// x axis points to the right of the board and the y axis away from the
+/// USB port, following `Orientation`.
+pub fn downhill_pixel(x: f32, y: f32) -> Option<u8> {
+    if (x * x + y * y).sqrt() < LEVEL_THRESHOLD {
+        return None;
+    }
+
+    // An axis reads positive when it points up, so downhill is (-x, -y).
+    Some(pixel_at_angle((-x).atan2(y).to_degrees()))
+}
+
+/// Returns the NeoPixel next to `from` in the direction of `to` the short way
+/// around the ten pixel ring, or `to` itself if they're the same pixel.
+/// Indexes past the end of the ring wrap around it.
+pub fn step_toward(from: u8, to: u8) -> u8 {
+    let count = PIXEL_ANGLES.len() as u8;
+    let (from, to) = (from % count, to % count);
+    let ahead = (to + count - from) % count;
+
+    match ahead {
+        0 => from,
+        _ if ahead <= count / 2 => (from + 1) % count,
+        _ => (from + count - 1) % count,
+    }
+}
+
+/// Acceleration in G for `detect_shake` that a gentle shake reaches. The
+/// board reads about 1G at rest because of gravity.
+pub const SHAKE_GENTLE_G: f32 = 1.5;
+/// Acceleration in G for `detect_shake` that only a hard jolt reaches.
+pub const SHAKE_HARD_G: f32 = 3.0;
+
+// Standard gravity in meters/second^2, used to convert readings to G.
+const GRAVITY: f32 = 9.80665;
+
+// Number of samples above the threshold that detect_shake counts as a shake,
+// so that a single bump isn't reported.
+const SHAKE_MIN_SAMPLES: usize = 3;
+
+/// Returns true if at least `min_samples` of the acceleration `samples`, in
+/// meters/second^2, have a total magnitude above `threshold_g`.
+pub fn is_shake(samples: &[(f32, f32, f32)], threshold_g: f32, min_samples: usize) -> bool {
+    samples.iter()
+        .filter(|&&(x, y, z)| (x * x + y * y + z * z).sqrt() / GRAVITY > threshold_g)
+        .count() >= min_samples
+}
+
+// The temperatures, in degrees Celsius, that show_temperature shows as pure
+// blue and pure red.
+const SHOW_TEMPERATURE_MIN_C: f64 = 15.0;
+const SHOW_TEMPERATURE_MAX_C: f64 = 35.0;
+
+/// A NeoPixel brightness from 0 (off) to 100 (full brightness), as taken by
+/// `set_brightness`. Levels are checked when the `Brightness` is created, so
+/// setting one can't fail on range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Brightness(u8);
+
+impl Brightness {
+    /// Full brightness, which the board starts with.
+    pub const MAX: Brightness = Brightness(100);
+    /// The NeoPixels are off whatever color they're set to.
+    pub const OFF: Brightness = Brightness(0);
+
+    /// Creates a new `Brightness` from a level from 0 to 100. Returns
+    /// `CpError::InvalidBrightness` for levels above 100.
+    pub fn new(level: u8) -> Result<Brightness, CpError> {
+        if level > 100 {
+            return Err(CpError::InvalidBrightness(level));
+        }
+        Ok(Brightness(level))
+    }
+
+    /// Creates a `Brightness` from a fraction of full brightness from 0.0
+    /// to 1.0, rounded to the nearest level. Fractions outside that range
+    /// are clamped.
+    pub fn from_fraction(fraction: f64) -> Brightness {
+        Brightness(map_range_clamped(fraction, 0.0, 1.0, 0.0, 100.0).round() as u8)
+    }
+
+    /// Returns the level, from 0 to 100.
+    pub fn level(&self) -> u8 {
+        self.0
+    }
+}
+
+/// A structure representing a single temperature reading.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Temperature {
+    celsius: f64,
+}
+
+impl Temperature {
+    /// Creates a new `Temperature` from degrees Celsius.
+    pub fn from_celsius(celsius: f64) -> Temperature {
+        Temperature { celsius }
+    }
+
+    /// Returns the temperature in degrees Celsius.
+    pub fn celsius(&self) -> f64 {
+        self.celsius
+    }
+
+    /// Returns the temperature in degrees Fahrenheit.
+    pub fn fahrenheit(&self) -> f64 {
+        self.celsius * 9.0 / 5.0 + 32.0
+    }
+
+    /// Returns the temperature in Kelvin.
+    pub fn kelvin(&self) -> f64 {
+        self.celsius + 273.15
+    }
+}
+
+/// Rescales `x` from the range `in_min`-`in_max` to `out_min`-`out_max`,
+/// like Arduino's `map` but without rounding to whole numbers. Either range
+/// can be inverted, so mapping 0-1023 onto 100-0 reverses the direction.
+/// Values outside the input range map outside the output range; use
+/// `map_range_clamped` to keep them inside it. An empty input range maps
+/// everything to `out_min`.
+pub fn map_range(x: f64, in_min: f64, in_max: f64, out_min: f64, out_max: f64) -> f64 {
+    if in_max == in_min {
+        return out_min;
+    }
+
+    out_min + (x - in_min) * (out_max - out_min) / (in_max - in_min)
+}
+
+/// Like `map_range`, but clamps the result to the output range.
+pub fn map_range_clamped(x: f64, in_min: f64, in_max: f64, out_min: f64, out_max: f64) -> f64 {
+    map_range(x, in_min, in_max, out_min, out_max).clamp(out_min.min(out_max), out_min.max(out_max))
+}
+
+/// Converts a 10-bit ADC reading of the thermistor into degrees Celsius
+/// using the beta equation. Readings of 0 and 1023 and above, which the
+/// thermistor only gives when it's disconnected or shorted, return NaN.
+pub fn therm_value_to_celsius(adc_value: u16) -> f64 {
+    if adc_value == 0 || adc_value >= 1023 {
+        return f64::NAN;
+    }
+
+    let resistance = THERM_SERIES_OHMS / (1023.0 / adc_value as f64 - 1.0);
+
+    let mut steinhart = (resistance / THERM_NOMINAL_OHMS).ln() / THERM_BETA;
+    steinhart += 1.0 / (THERM_NOMINAL_C + 273.15);
+
+    1.0 / steinhart - 273.15
+}
+
+/// Returns the difference between the largest and smallest of `samples`, or
+/// 0 if there are none.
+pub fn peak_to_peak(samples: &[u16]) -> u16 {
+    match (samples.iter().max(), samples.iter().min()) {
+        (Some(max), Some(min)) => max - min,
+        _ => 0,
+    }
+}
+
+// Number of microphone samples in each peak-to-peak reading detect_clap
+// takes, about 80 ms at the default sampling interval.
+const CLAP_SAMPLES: usize = 4;
+
+/// Returns true if the sound level jumped from `previous_peak` to `peak` the
+/// way a clap does: `peak` is above `threshold` and `previous_peak` was below
+/// half of it, so sustained or slowly rising noise doesn't count.
+pub fn is_clap(previous_peak: u16, peak: u16, threshold: u16) -> bool {
+    peak > threshold && previous_peak < threshold / 2
+}
+
+/// How far above its threshold the light level has to rise before
+/// `night_light` turns the ring off again, so that it doesn't flicker when
+/// the light hovers around the threshold.
+pub const NIGHT_LIGHT_HYSTERESIS: u16 = 50;
+
+// Brightness night_light lights the ring at, gentle enough for a bedroom.
+const NIGHT_LIGHT_BRIGHTNESS: Brightness = Brightness(20);
+
+// How often night_light reads the light sensor, in milliseconds.
+const NIGHT_LIGHT_POLL_MS: u64 = 100;
+
+/// Returns whether a night light should be lit at the raw light `level`,
+/// given whether it was lit before. It turns on once the level drops below
+/// `on_threshold`, and only turns off again once it rises above
+/// `on_threshold` plus `NIGHT_LIGHT_HYSTERESIS`.
+pub fn night_light_lit(was_lit: bool, level: u16, on_threshold: u16) -> bool {
+    if was_lit {
+        level <= on_threshold.saturating_add(NIGHT_LIGHT_HYSTERESIS)
+    } else {
+        level < on_threshold
+    }
+}
+
+// Number of microphone samples snapshot takes the sound level from.
+const SNAPSHOT_SOUND_SAMPLES: usize = 10;
+
+/// A structure representing one reading of each of the board's sensors, as
+/// returned by `snapshot`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snapshot {
+    /// The temperature in degrees Celsius.
+    pub temperature_c: f64,
+    /// The raw light level, from 0 (dark) to 1023 (bright).
+    pub light: u16,
+    /// The peak-to-peak sound level, as returned by `read_sound_peak`.
+    pub sound: u16,
+    /// The x, y and z acceleration in meters/second^2.
+    pub accel: (f32, f32, f32),
+}
+
+impl Snapshot {
+    /// Returns the snapshot as a JSON object with one field per reading,
+    /// the acceleration being an array of x, y and z, for logging or
+    /// sending it on. JSON has no NaN or infinity, so any reading that is
+    /// NaN or infinite, such as a temperature that couldn't be converted,
+    /// is written as `null`.
+    pub fn to_json(&self) -> String {
+        let (x, y, z) = self.accel;
+
+        format!(
+            "{{\"temperature_c\":{},\"light\":{},\"sound\":{},\"accel\":[{},{},{}]}}",
+            json_number(self.temperature_c), self.light, self.sound, json_number(x), json_number(y), json_number(z),
+        )
+    }
+}
+
+// Formats `value` as a JSON number, which can't be NaN or infinite.
+fn json_number<T: fmt::Display + Into<f64> + Copy>(value: T) -> String {
+    if value.into().is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+// Tone self_test plays, in hz and milliseconds.
+const SELF_TEST_TONE_HZ: u16 = 440;
+const SELF_TEST_TONE_MS: u16 = 200;
+
+// How long self_test shows each color on the ring, in milliseconds.
+const SELF_TEST_PIXEL_MS: u64 = 200;
+
+// Range of accelerometer magnitudes, in g, that self_test accepts. A board
+// at rest reads about 1g.
+const SELF_TEST_ACCEL_MIN_G: f32 = 0.5;
+const SELF_TEST_ACCEL_MAX_G: f32 = 1.5;
+
+// Range of temperatures, in degrees Celsius, that self_test accepts. The
+// thermistor reads far outside this when it's disconnected or shorted.
+const SELF_TEST_TEMPERATURE_MIN_C: f64 = -20.0;
+const SELF_TEST_TEMPERATURE_MAX_C: f64 = 80.0;
+
+/// A structure holding whether each part of the board passed `self_test`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelfTestReport {
+    /// The ring was cycled through red, green and blue. This only checks
+    /// that the commands were sent, so watch the ring to be sure.
+    pub pixels: bool,
+    /// A tone was played. As with the pixels, listen for it to be sure.
+    pub speaker: bool,
+    /// The accelerometer replied with a reading of about 1g.
+    pub accelerometer: bool,
+    /// The thermistor replied with a plausible temperature.
+    pub temperature: bool,
+    /// The light sensor replied.
+    pub light: bool,
+    /// The firmware replied with its implementation version.
+    pub firmware: bool,
+}
+
+impl SelfTestReport {
+    /// Returns true if every part passed.
+    pub fn passed(&self) -> bool {
+        self.pixels && self.speaker && self.accelerometer && self.temperature && self.light && self.firmware
+    }
+}
+
+// Turns the result of one self_test step into whether it passed. A board
+// that doesn't reply, or replies with garbage, fails the step, but errors
+// from the connection itself are passed on.
+fn self_test_step<T, F: FnOnce(T) -> bool>(result: Result<T, CpError>, check: F) -> Result<bool, CpError> {
+    match result {
+        Ok(value) => Ok(check(value)),
+        Err(CpError::Timeout) | Err(CpError::MalformedReply(_)) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// The position of the slide switch, as seen with the USB port at the top.
+/// `Left` is the side marked "+" on the board and `Right` the side marked "-".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwitchPosition {
+    Left,
+    Right,
+}
+
+/// Firmata pin mode for an input with the internal pull-up enabled. The
+/// firmata crate predates this mode so it doesn't define it.
+pub const INPUT_PULLUP: u8 = 0x0B;
+
+// Firmata message that resets the firmware to its power-on state. Like
+// INPUT_PULLUP it isn't defined by the firmata crate.
+const SYSTEM_RESET: u8 = 0xFF;
+
+/// A structure representing the implementation version of the Circuit
+/// Playground firmware. Versions compare in major, minor, bugfix order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub bugfix: u8,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.bugfix)
+    }
+}
+
+/// A reply from the Circuit Playground firmware.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CpEvent {
+    /// An accelerometer reading of x, y and z in meters/second^2.
+    Accel(f32, f32, f32),
+    /// A tap state.
+    Tap(TapState),
+    /// A capacitive touch input and its reading.
+    Cap(u8, i32),
+    /// The result of a color sense.
+    Color(Color),
+    /// The implementation version of the firmware.
+    Version(Version),
+}
+
+impl CpEvent {
+    // Decodes the bytes between START_SYSEX and END_SYSEX of a Circuit
+    // Playground reply, returning `None` for anything else.
+    fn from_sysex(frame: &[u8]) -> Option<CpEvent> {
+        if frame.len() < 2 || frame[0] != CP_COMMAND {
+            return None;
+        }
+
+        CpEvent::from_reply(frame[1], &frame[2..])
+    }
+
+    // Decodes the `data` of a Circuit Playground reply with the sub-command
+    // `sub`, returning `None` if it isn't a reply or is too short.
+    fn from_reply(sub: u8, data: &[u8]) -> Option<CpEvent> {
+        match sub {
+            CP_ACCEL_READ_REPLY => protocol::decode_accel_reply(data).map(|(x, y, z)| CpEvent::Accel(x, y, z)),
+            CP_ACCEL_TAP_REPLY => protocol::decode_tap_reply(data).map(|register| CpEvent::Tap(TapState::from_register(register))),
+            CP_CAP_REPLY => protocol::decode_cap_reply(data).map(|(pin, value)| CpEvent::Cap(pin, value)),
+            CP_SENSECOLOR_REPLY => protocol::decode_color_reply(data).map(|(r, g, b)| CpEvent::Color(Color::new(r, g, b))),
+            CP_IMPL_VERS_REPLY => protocol::decode_version_reply(data)
+                .map(|(major, minor, bugfix)| CpEvent::Version(Version { major, minor, bugfix })),
+            _ => None,
+        }
+    }
+}
+
+// A message read from the board by read_message.
+enum Message {
+    // The bytes between START_SYSEX and END_SYSEX.
+    Sysex(Vec<u8>),
+    // A port number and the levels of its eight pins.
+    Digital(u8, u16),
+    // An analog channel and its value.
+    Analog(u8, u16),
+}
+
+// Returns the level of `pin` from the value of a digital message for its port.
+fn port_pin_level(value: u16, pin: u8) -> bool {
+    value >> (pin % 8) & 0x01 != 0
+}
+
+// Default acceleration an axis needs before read_orientation will report it,
+// in meters/second^2 (about 0.7G).
+const ORIENTATION_THRESHOLD: f32 = 7.0;
+
+/// The number of NeoPixels on a Circuit Playground.
+pub const DEFAULT_PIXEL_COUNT: u8 = 10;
+
+/// Current a NeoPixel draws for one color channel at full brightness, in
+/// milliamps, so a pixel at full white draws about 60 mA.
+pub const PIXEL_CHANNEL_MA: u32 = 20;
+
+/// How many times a command is tried again when writing it fails with a
+/// transient error, unless changed with `set_write_retries`.
+pub const DEFAULT_WRITE_RETRIES: u32 = 2;
+
+// How long to wait before trying a failed write again, in milliseconds.
+const WRITE_RETRY_DELAY_MS: u64 = 5;
+
+/// How many times `reconnect` tries to open the serial port.
+pub const RECONNECT_ATTEMPTS: u32 = 6;
+
+/// How long `reconnect` waits after its first failed attempt, in
+/// milliseconds. The wait doubles after every attempt.
+pub const RECONNECT_DELAY_MS: u64 = 100;
+
+// How long each brightness step of a pulse is shown, in milliseconds.
+const PULSE_FRAME_MS: u64 = 20;
+
+/// The longest `drain_input` keeps reading for, in milliseconds.
+pub const DRAIN_TIMEOUT_MS: u64 = 50;
+
+/// How long to wait for the board to answer a query before giving up, unless
+/// changed with `set_reply_timeout` or `CircuitPlaygroundBuilder::timeout`.
+pub const DEFAULT_REPLY_TIMEOUT: Duration = Duration::from_millis(1000);
+
+type AccelCallback = Box<dyn FnMut(f32, f32, f32) + Send>;
+type TapCallback = Box<dyn FnMut(TapState) + Send>;
+type CapCallback = Box<dyn FnMut(i32) + Send>;
+type ButtonCallback = Box<dyn FnMut(bool) + Send>;
+
+// A button callback along with the last state its port reported, which is
+// `None` until the first report.
+struct Button {
+    callback: ButtonCallback,
+    pressed: Option<bool>,
+}
+
+// Wraps `transport` in a `Board` without the Firmata handshake, assuming
+// it has 32 pins, for `with_transport`.
+fn transport_board<T: Transport + 'static>(transport: T) -> (Board<Box<dyn Transport>>, connection::Connected) {
+    let (connection, connected) = connection::Watched::wrap(Box::new(transport));
+    let pins = (0..32).map(|_| Pin { modes: vec![], analog: false, value: 0, mode: 0 }).collect();
+    let board = Board {
+        connection: Box::new(connection),
+        pins,
+        i2c_data: vec![],
+        protocol_version: String::new(),
+        firmware_name: String::new(),
+        firmware_version: String::new(),
+    };
+
+    (board, connected)
+}
+
+/// A trait for connections a `CircuitPlayground` can talk to the board over.
+/// It is implemented for every `io::Read + io::Write + Send` type, such as
+/// the serial ports opened by `CircuitPlayground::new`.
+pub trait Transport: io::Read + io::Write + Send {}
+
+impl<T: io::Read + io::Write + Send> Transport for T {}
 
 pub struct CircuitPlayground {
-    win_board: Board<serial::windows::COMPort>,
-    unix_board: Board<serial::unix::TTYPort>,
+    board: Board<Box<dyn Transport>>,
+    accel_callback: Option<AccelCallback>,
+    tap_callback: Option<TapCallback>,
+    orientation: Orientation,
+    orientation_threshold: f32,
+    cap_threshold: u64,
+    cap_baselines: HashMap<u8, i32>,
+    light_smoother: Option<LightSmoother>,
+    cap_callbacks: HashMap<u8, CapCallback>,
+    button_callbacks: HashMap<u8, Button>,
+    input: Vec<u8>,
+    version: Option<Version>,
+    reply_timeout: Duration,
+    // Set when a stream is started or stopped, as the board may still have
+    // readings on their way that the next query has to drain first.
+    streamed: bool,
+    pixel_count: u8,
+    // The settings the board was connected with, used by `reconnect`.
+    builder: Option<CircuitPlaygroundBuilder>,
+    connected: Arc<AtomicBool>,
+    brightness: Option<Brightness>,
+    accel_range: Option<AccelRange>,
+    tap_config: Option<(ClickType, u8)>,
+    // The color each NeoPixel was last set to, used by `fade_to`.
+    pixels: Vec<Color>,
+    // The NeoPixel the `marble` is on.
+    marble: u8,
+    // Notes queued by `schedule_note`, and the one `tick` last started.
+    scheduled_notes: Vec<ScheduledNote>,
+    sounding_note: Option<ScheduledNote>,
+    tilt_dead_zone: f32,
+    write_retries: u32,
+    color_order: ColorOrder,
+}
+
+// Number of bytes framing adds around the data of a sub-command.
+const CP_COMMAND_FRAMING_LEN: usize = 4;
+
+// Largest data send_cp_command frames on the stack. Every command this crate
+// sends fits, only longer ones passed to send_raw need the heap.
+const CP_COMMAND_STACK_DATA_LEN: usize = 28;
+
+// Writes the Circuit Playground sub-command `sub` with `data` to the start of
+// `buf` as START_SYSEX, CP_COMMAND, sub, data..., END_SYSEX, returning the
+// number of bytes written. Sysex data can only carry 7 bits per byte, so each
+// data byte is masked to keep it from being read as the start of another
+// message. Panics if `buf` is too short.
+fn write_cp_command(buf: &mut [u8], sub: u8, data: &[u8]) -> usize {
+    let len = data.len() + CP_COMMAND_FRAMING_LEN;
+
+    buf[0] = START_SYSEX;
+    buf[1] = CP_COMMAND;
+    buf[2] = sub;
+    for (out, byte) in buf[3..len - 1].iter_mut().zip(data) {
+        *out = byte & 0x7F;
+    }
+    buf[len - 1] = END_SYSEX;
+    len
+}
+
+// Appends the framed sub-command to `buf`, as `write_cp_command` does.
+fn frame_cp_command(buf: &mut Vec<u8>, sub: u8, data: &[u8]) {
+    let start = buf.len();
+
+    buf.resize(start + data.len() + CP_COMMAND_FRAMING_LEN, 0);
+    write_cp_command(&mut buf[start..], sub, data);
+}
+
+// Returns true for write errors that are worth trying again, as a busy or
+// briefly stalled USB serial port gives.
+fn is_transient(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::Interrupted
+}
+
+// Returns an error listing the valid inputs if `pin` isn't a cap touch input.
+fn check_cap_pin(pin: u8) -> Result<(), CpError> {
+    if !CAP_PINS.contains(&pin) {
+        return Err(CpError::InvalidCapPin(pin));
+    }
+    Ok(())
 }
 
 impl CircuitPlayground {
-    pub fn new(port_id: &str) -> Result<CircuitPlayground, Box<dyn Error> {
-        let mut sp = serial::open(port_id)?;
-
-        sp.reconfigure(&|settings| {
-            settings.set_baud_rate(Baud57600)?;
-            settings.set_char_size(Bits8);
-            settings.set_parity(ParityNone);
-            settings.set_stop_bits(Stop1);
-            settings.set_flow_control(FlowNone);
-            Ok(())
-        })?;
+    /// Connects to the Circuit Playground on the serial port `port_id` with
+    /// the default settings. Use `CircuitPlaygroundBuilder` to change them.
+    pub fn new(port_id: &str) -> Result<CircuitPlayground, CpError> {
+        CircuitPlaygroundBuilder::new().port(port_id).build()
+    }
 
-        let mut board = firmata::Board::new(Box::new(sp))?;
+    // Wraps a board that has already been connected to.
+    fn from_board(board: Board<Box<dyn Transport>>, reply_timeout: Duration, connected: Arc<AtomicBool>) -> CircuitPlayground {
+        CircuitPlayground{
+            board,
+            accel_callback: None,
+            tap_callback: None,
+            orientation: Orientation::FaceUp,
+            orientation_threshold: ORIENTATION_THRESHOLD,
+            cap_threshold: CAP_THRESHOLD,
+            cap_baselines: HashMap::new(),
+            light_smoother: None,
+            cap_callbacks: HashMap::new(),
+            button_callbacks: HashMap::new(),
+            input: vec![],
+            version: None,
+            reply_timeout,
+            streamed: false,
+            pixel_count: DEFAULT_PIXEL_COUNT,
+            builder: None,
+            connected,
+            brightness: None,
+            accel_range: None,
+            tap_config: None,
+            pixels: vec![Color::BLACK; DEFAULT_PIXEL_COUNT as usize],
+            marble: 0,
+            scheduled_notes: vec![],
+            sounding_note: None,
+            tilt_dead_zone: TILT_DEAD_ZONE,
+            write_retries: DEFAULT_WRITE_RETRIES,
+            color_order: ColorOrder::Rgb,
+        }
+    }
 
-        println!("firmware version {}", board.firmware_version());
-        println!("firmware name {}", board.firmware_name());
-        println!("protocol version {}", board.protocol_version());
+    /// Wraps `transport` without going through the Firmata handshake, so a
+    /// `MockTransport` can stand in for a board in tests. Since the board's
+    /// capabilities aren't queried, it is assumed to have 32 pins.
+    pub fn with_transport<T: Transport + 'static>(transport: T) -> CircuitPlayground {
+        let (board, connected) = transport_board(transport);
+        CircuitPlayground::from_board(board, DEFAULT_REPLY_TIMEOUT, connected)
+    }
 
-        let os_type = env::consts::OS;
+    /// Finds the first Circuit Playground among the serial ports listed by
+    /// `list_ports` and connects to it. Returns `CpError::NoBoardFound`, with
+    /// the names of the ports that were checked, if there is none.
+    pub fn autodetect() -> Result<CircuitPlayground, CpError> {
+        CircuitPlaygroundBuilder::new().build()
+    }
 
-        if os_type == "windows" {
-            Ok(CircuitPlayground{win_board: board, unix_board: None})
-        } else {
-            Ok(CircuitPlayground{win_board: None, unix_board: board})
-        }  
-    } 
-}
\ No newline at end of file
+    /// Returns the name of the serial port the board is connected on, or
+    /// `None` if it was connected with `with_transport`.
+    pub fn port_name(&self) -> Option<&str> {
+        self.builder.as_ref().and_then(|builder| builder.port.as_deref())
+    }
+
+    /// Returns false once reading from or writing to the board has failed,
+    /// which usually means it was unplugged. Use `reconnect` to connect again.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Opens the serial port again after the connection was lost, retrying
+    /// up to `RECONNECT_ATTEMPTS` times with a doubling delay starting at
+    /// `RECONNECT_DELAY_MS`. Once connected the board is reset, and the
+    /// brightness, accelerometer range and tap configuration set earlier are
+    /// sent again. Streams have to be restarted. Returns `CpError::NoPort` if
+    /// the board wasn't connected through a serial port.
+    pub fn reconnect(&mut self) -> Result<(), CpError> {
+        let builder = match self.builder {
+            Some(ref builder) => builder.clone(),
+            None => return Err(CpError::NoPort),
+        };
+        let port = builder.port.clone().ok_or(CpError::NoPort)?;
+
+        let mut delay = Duration::from_millis(RECONNECT_DELAY_MS);
+        let mut attempt = 1;
+
+        let (board, connected) = loop {
+            match builder.open(&port) {
+                Ok(opened) => break opened,
+                Err(err) if attempt >= RECONNECT_ATTEMPTS => return Err(err),
+                Err(_) => {
+                    thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+            }
+        };
+
+        self.restore(board, connected)
+    }
+
+    // Switches over to the newly opened `board`, resets it and sends the
+    // settings made over the old connection again.
+    fn restore(&mut self, board: Board<Box<dyn Transport>>, connected: connection::Connected) -> Result<(), CpError> {
+        self.board = board;
+        self.connected = connected;
+        self.reset()?;
+
+        if let Some(brightness) = self.brightness {
+            self.set_brightness(brightness)?;
+        }
+        if let Some(range) = self.accel_range {
+            self.set_accel_range(range)?;
+        }
+        if let Some((click_type, threshold)) = self.tap_config {
+            self.set_tap_config(click_type, threshold)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of NeoPixels, which is 10 unless it was changed with
+    /// `CircuitPlaygroundBuilder::pixel_count`.
+    pub fn pixel_count(&self) -> u8 {
+        self.pixel_count
+    }
+
+    /// Sets how long methods that wait for a reply from the board, such as
+    /// `read_accelerometer`, wait before returning `CpError::Timeout`.
+    pub fn set_reply_timeout(&mut self, timeout: Duration) {
+        self.reply_timeout = timeout;
+    }
+
+    /// Sets the order the color channels of NeoPixel colors are sent in, for
+    /// boards whose red and green come out swapped. The builder's
+    /// `color_order` sets it when connecting.
+    pub fn set_color_order(&mut self, order: ColorOrder) {
+        self.color_order = order;
+    }
+
+    /// Sets how many times a command is tried again when writing it to the
+    /// board fails with `WouldBlock` or `Interrupted`, which flaky USB
+    /// connections can give now and then. Other errors are returned
+    /// straight away. The default is `DEFAULT_WRITE_RETRIES`.
+    pub fn set_write_retries(&mut self, retries: u32) {
+        self.write_retries = retries;
+    }
+
+    /// Returns how long methods wait for a reply from the board.
+    pub fn reply_timeout(&self) -> Duration {
+        self.reply_timeout
+    }
+
+    /// Sets the NeoPixel at `index` (0-9, or up to the builder's
+    /// `pixel_count` less one) to `color`. The change is not
+    /// visible until the pixels are shown, so several pixels can be set
+    /// before updating the board.
+    pub fn set_pixel<C: Into<Color>>(&mut self, index: u8, color: C) -> Result<(), CpError> {
+        self.check_pixel(index)?;
+
+        let color = color.into();
+        let data = color.to_firmata_bytes_in(self.color_order);
+
+        self.send_cp_command(CP_PIXEL_SET, &[index, data[0], data[1], data[2], data[3]])?;
+        self.pixels[index as usize] = color;
+        Ok(())
+    }
+
+    /// Like `set_pixel`, but applies gamma correction to `color` first.
+    pub fn set_pixel_gamma<C: Into<Color>>(&mut self, index: u8, color: C) -> Result<(), CpError> {
+        self.set_pixel(index, color.into().gamma_correct())
+    }
+
+    /// Like `set_pixel`, but takes the color as a hue in degrees and a
+    /// saturation and value from 0.0 to 1.0, as for `Color::from_hsv`.
+    pub fn set_pixel_hsv(&mut self, index: u8, h: f32, s: f32, v: f32) -> Result<(), CpError> {
+        self.set_pixel(index, Color::from_hsv(h, s, v))
+    }
+
+    /// Updates the NeoPixels with the colors sent by `set_pixel`. Nothing
+    /// changes on the board until this is called, so set every pixel that
+    /// should change first and then call `show_pixels` once.
+    pub fn show_pixels(&mut self) -> Result<(), CpError> {
+        self.send_cp_command(CP_PIXEL_SHOW, &[])?;
+        self.board.connection.flush()?;
+        Ok(())
+    }
+
+    /// Starts a batch of pixel commands that are sent in a single write, for
+    /// example `cp.batch().set_pixel(0, Color::RED).show().commit()`.
+    pub fn batch(&mut self) -> Batch<'_> {
+        Batch::new(self)
+    }
+
+    /// Turns off all of the NeoPixels. Like `set_pixel`, the change is only
+    /// visible after `show_pixels` is called; use `clear_and_show` to do both.
+    pub fn clear_pixels(&mut self) -> Result<(), CpError> {
+        self.send_cp_command(CP_PIXEL_CLEAR, &[])?;
+        self.clear_tracked_pixels();
+        Ok(())
+    }
+
+    // Forgets the colors the NeoPixels were set to, for when the firmware
+    // has turned them off.
+    pub(crate) fn clear_tracked_pixels(&mut self) {
+        self.pixels = vec![Color::BLACK; self.pixel_count as usize];
+    }
+
+    // An unlit frame for the helpers that follow the ring, which map pixels
+    // to `PIXEL_ANGLES` and so only work with ten of them.
+    fn ring_frame(&self) -> Result<Vec<Color>, CpError> {
+        if self.pixel_count as usize != PIXEL_ANGLES.len() {
+            return Err(CpError::NotARing(self.pixel_count));
+        }
+        Ok(vec![Color::BLACK; self.pixel_count as usize])
+    }
+
+    /// Turns off all of the NeoPixels and immediately shows the change.
+    pub fn clear_and_show(&mut self) -> Result<(), CpError> {
+        self.clear_pixels()?;
+        self.show_pixels()
+    }
+
+    /// Sets the brightness of all NeoPixels to `brightness`.
+    pub fn set_brightness(&mut self, brightness: Brightness) -> Result<(), CpError> {
+        self.send_cp_command(CP_PIXEL_BRIGHTNESS, &[brightness.level()])?;
+        self.brightness = Some(brightness);
+        Ok(())
+    }
+
+    /// Sets all the NeoPixels to `color` and shows the change. The firmware
+    /// has no command to set several pixels at once, so this sends one
+    /// CP_PIXEL_SET message per pixel followed by a single show.
+    pub fn fill<C: Into<Color>>(&mut self, color: C) -> Result<(), CpError> {
+        let color = color.into();
+
+        for index in 0..self.pixel_count {
+            self.set_pixel(index, color)?;
+        }
+
+        self.show_pixels()
+    }
+
+    /// Like `fill`, but applies gamma correction to `color` first, which
+    /// gives more even fills at low brightness.
+    pub fn fill_gamma<C: Into<Color>>(&mut self, color: C) -> Result<(), CpError> {
+        self.fill(color.into().gamma_correct())
+    }
+
+    /// Sets each NeoPixel to the matching entry of `colors` and shows the
+    /// change, which makes it a natural way to draw animation frames. There
+    /// must be exactly one color per pixel, ten unless the builder's
+    /// `pixel_count` was changed.
+    pub fn set_pixels(&mut self, colors: &[Color]) -> Result<(), CpError> {
+        if colors.len() != self.pixel_count as usize {
+            return Err(CpError::InvalidPixelCount { expected: self.pixel_count as usize, found: colors.len() });
+        }
+
+        for (index, color) in colors.iter().enumerate() {
+            self.set_pixel(index as u8, *color)?;
+        }
+
+        self.show_pixels()
+    }
+
+    /// Crossfades every NeoPixel from the color it was last set to towards
+    /// `target` over `duration_ms` milliseconds, showing `steps` frames
+    /// along the way, which are spaced out along `easing` as in `pulse`. The
+    /// last frame is exactly `target`. Only colors set through this crate
+    /// are known, so pixels changed with `send_raw` fade from the wrong color.
+    pub fn fade_to<C: Into<Color>>(&mut self, target: C, duration_ms: u64, steps: u32, easing: Easing) -> Result<(), CpError> {
+        let target = target.into();
+        let steps = steps.max(1);
+        let delay = Duration::from_millis(duration_ms / steps as u64);
+        let start = self.pixels.clone();
+
+        for step in 1..=steps {
+            let t = apply_easing(step as f32 / steps as f32, easing);
+            let frame: Vec<Color> = start.iter().map(|color| color.blend(target, t)).collect();
+
+            self.set_pixels(&frame)?;
+            thread::sleep(delay);
+        }
+        Ok(())
+    }
+
+    /// Estimates the current in milliamps the NeoPixels would draw showing
+    /// `colors`, from `PIXEL_CHANNEL_MA` per channel. It assumes a brightness
+    /// of 100, so all ten pixels at full white come to about 600 mA.
+    pub fn estimate_current_ma(&self, colors: &[Color]) -> u32 {
+        let total: u32 = colors.iter()
+            .map(|color| color.r as u32 + color.g as u32 + color.b as u32)
+            .sum();
+
+        total * PIXEL_CHANNEL_MA / 255
+    }
+
+    /// Like `set_pixels`, but if showing `colors` would draw more than
+    /// `max_ma` milliamps, as estimated by `estimate_current_ma`, every color
+    /// is scaled down by the same factor to stay under it. This avoids
+    /// brownouts on weak USB ports.
+    pub fn set_pixels_limited(&mut self, colors: &[Color], max_ma: u32) -> Result<(), CpError> {
+        let current = self.estimate_current_ma(colors);
+        if current <= max_ma {
+            return self.set_pixels(colors);
+        }
+
+        let factor = max_ma as f32 / current as f32;
+        let frame: Vec<Color> = colors.iter().map(|color| color.scale(factor)).collect();
+        self.set_pixels(&frame)
+    }
+
+    /// Flashes all the NeoPixels `times` times, lighting them with `color`
+    /// for `on_ms` milliseconds and then turning them off for `off_ms`. If a
+    /// write fails the blinking stops there and the error is returned.
+    pub fn blink<C: Into<Color>>(&mut self, color: C, times: u32, on_ms: u64, off_ms: u64) -> Result<(), CpError> {
+        let color = color.into();
+
+        for _ in 0..times {
+            self.fill(color)?;
+            thread::sleep(Duration::from_millis(on_ms));
+            self.clear_and_show()?;
+            thread::sleep(Duration::from_millis(off_ms));
+        }
+        Ok(())
+    }
+
+    /// Spreads the whole `color_wheel` around the NeoPixels and turns it
+    /// one step every `delay_ms` milliseconds, for `iterations` frames. 256
+    /// frames turn the rainbow all the way around once.
+    pub fn rainbow_cycle(&mut self, iterations: u32, delay_ms: u64) -> Result<(), CpError> {
+        let mut frames = 0;
+
+        self.rainbow_cycle_until(delay_ms, || {
+            frames += 1;
+            frames > iterations
+        })
+    }
+
+    /// Like `rainbow_cycle`, but keeps going until `stop` returns true. It is
+    /// called before every frame, so callers can break out at any time.
+    pub fn rainbow_cycle_until<F: FnMut() -> bool>(&mut self, delay_ms: u64, mut stop: F) -> Result<(), CpError> {
+        let count = self.pixel_count as usize;
+        let mut offset: u8 = 0;
+
+        while !stop() {
+            let mut frame = vec![Color::BLACK; count];
+            for (index, color) in frame.iter_mut().enumerate() {
+                *color = color_wheel(((index * 256 / count) as u8).wrapping_add(offset));
+            }
+
+            self.set_pixels(&frame)?;
+            thread::sleep(Duration::from_millis(delay_ms));
+            offset = offset.wrapping_add(1);
+        }
+        Ok(())
+    }
+
+    /// Lights every third NeoPixel with `color` and moves the pattern along
+    /// one pixel every `delay_ms` milliseconds, for `iterations` frames, like
+    /// the `theaterChase` example in Adafruit's NeoPixel library.
+    pub fn theater_chase<C: Into<Color>>(&mut self, color: C, iterations: u32, delay_ms: u64) -> Result<(), CpError> {
+        let mut frames = 0;
+
+        self.theater_chase_until(color, delay_ms, || {
+            frames += 1;
+            frames > iterations
+        })
+    }
+
+    /// Like `theater_chase`, but keeps going until `stop` returns true. It is
+    /// called before every frame, so callers can break out at any time.
+    pub fn theater_chase_until<C, F>(&mut self, color: C, delay_ms: u64, mut stop: F) -> Result<(), CpError>
+        where C: Into<Color>, F: FnMut() -> bool {
+        let color = color.into();
+        let mut phase = 0;
+
+        while !stop() {
+            let mut frame = vec![Color::BLACK; self.pixel_count as usize];
+            for (index, pixel) in frame.iter_mut().enumerate() {
+                if index % 3 == phase {
+                    *pixel = color;
+                }
+            }
+
+            self.set_pixels(&frame)?;
+            thread::sleep(Duration::from_millis(delay_ms));
+            phase = (phase + 1) % 3;
+        }
+        Ok(())
+    }
+
+    /// Moves a comet of `color` around the ring one pixel every `delay_ms`
+    /// milliseconds, for `iterations` frames. The head is followed by `tail`
+    /// pixels (at most one less than the number of pixels) that get dimmer
+    /// further back, the nth one having its channels divided by n + 1.
+    pub fn comet<C: Into<Color>>(&mut self, color: C, tail: u8, iterations: u32, delay_ms: u64) -> Result<(), CpError> {
+        let color = color.into();
+        let count = self.pixel_count as usize;
+        let tail = (tail as usize).min(count - 1);
+
+        for frame_index in 0..iterations {
+            let head = frame_index as usize % count;
+            let mut frame = vec![Color::BLACK; count];
+
+            for distance in 0..=tail {
+                let divisor = distance as u8 + 1;
+                frame[(head + count - distance) % count] = Color::new(color.r / divisor, color.g / divisor, color.b / divisor);
+            }
+
+            self.set_pixels(&frame)?;
+            thread::sleep(Duration::from_millis(delay_ms));
+        }
+        Ok(())
+    }
+
+    /// Lights all the NeoPixels with `color` and fades the brightness up
+    /// from 0 to 100 and back down, `cycles` times, each taking `period_ms`
+    /// milliseconds. Both halves of a cycle follow `easing`. `Easing::Sine`
+    /// breathes along a sine wave and is the usual choice,
+    /// `Easing::EaseInOut` lingers longer at the top and bottom, and
+    /// `Easing::Linear` bounces between them. The brightness is
+    /// left at 100.
+    pub fn pulse<C: Into<Color>>(&mut self, color: C, period_ms: u64, cycles: u32, easing: Easing) -> Result<(), CpError> {
+        let color = color.into();
+        let steps = (period_ms / PULSE_FRAME_MS).max(1);
+
+        for _ in 0..cycles {
+            for step in 0..steps {
+                // Goes from 0.0 up to 1.0 halfway through and back down.
+                let phase = 1.0 - (2.0 * step as f32 / steps as f32 - 1.0).abs();
+                let level = apply_easing(phase, easing) as f64;
+
+                // The firmware scales the stored pixel colors when the
+                // brightness changes, so they're set again every frame.
+                self.set_brightness(Brightness::from_fraction(level))?;
+                self.fill(color)?;
+                thread::sleep(Duration::from_millis(PULSE_FRAME_MS));
+            }
+        }
+
+        self.set_brightness(Brightness::MAX)?;
+        self.fill(color)
+    }
+
+    /// Turns the ring into a gauge showing `fraction` (0.0-1.0) as a share of
+    /// the NeoPixels lit with `color`, and shows the change. The pixel past
+    /// the last full one is lit dimmer by however much of it is covered.
+    /// Fractions outside 0.0-1.0 are clamped.
+    pub fn show_progress<C: Into<Color>>(&mut self, fraction: f32, color: C) -> Result<(), CpError> {
+        let color = color.into();
+        let lit = fraction.clamp(0.0, 1.0) * self.pixel_count as f32;
+
+        let mut frame = vec![Color::BLACK; self.pixel_count as usize];
+        for (index, pixel) in frame.iter_mut().enumerate() {
+            *pixel = color.scale(lit - index as f32);
+        }
+
+        self.set_pixels(&frame)
+    }
+
+    /// Plays a tone of `frequency` hz on the speaker for `duration_ms`
+    /// milliseconds. Both values are sent as two 7-bit bytes, so anything
+    /// above 16383 (about 16khz for the frequency) is rejected. This returns
+    /// straight away, the firmware times the tone. A duration of 0 plays
+    /// until `stop_tone`, see `start_tone`.
+    pub fn play_tone(&mut self, frequency: u16, duration_ms: u16) -> Result<(), CpError> {
+        if frequency > 0x3FFF {
+            return Err(CpError::InvalidFrequency(frequency));
+        }
+        if duration_ms > 0x3FFF {
+            return Err(CpError::InvalidDuration(duration_ms));
+        }
+
+        self.send_cp_command(CP_TONE, &[
+            frequency as u8,
+            (frequency >> 7) as u8,
+            duration_ms as u8,
+            (duration_ms >> 7) as u8,
+        ])
+    }
+
+    /// Starts playing a tone of `frequency` hz that keeps going until
+    /// `stop_tone` is called or another tone is played. Like `play_tone` it
+    /// returns straight away, which suits interactive instruments.
+    pub fn start_tone(&mut self, frequency: u16) -> Result<(), CpError> {
+        self.play_tone(frequency, 0)
+    }
+
+    /// Slides the speaker from `start_hz` to `end_hz` over `duration_ms`
+    /// milliseconds in `steps` tones, spaced according to `curve`, for
+    /// sirens and other effects. Frequencies are capped at 16383 hz.
+    pub fn sweep_tone(&mut self, start_hz: u16, end_hz: u16, duration_ms: u16, steps: u32, curve: SweepCurve) -> Result<(), CpError> {
+        let steps = steps.max(1);
+        let step_ms = (duration_ms as u32 / steps).clamp(1, 0x3FFF) as u16;
+        // A logarithmic sweep can't start or end at 0 hz.
+        let start = start_hz.clamp(1, 0x3FFF) as f64;
+        let end = end_hz.clamp(1, 0x3FFF) as f64;
+
+        for step in 0..steps {
+            let t = if steps > 1 { step as f64 / (steps - 1) as f64 } else { 0.0 };
+            let frequency = match curve {
+                SweepCurve::Linear => start + (end - start) * t,
+                SweepCurve::Logarithmic => start * (end / start).powf(t),
+            };
+
+            self.play_tone(frequency.round() as u16, step_ms)?;
+            thread::sleep(Duration::from_millis(step_ms as u64));
+        }
+        Ok(())
+    }
+
+    /// Stops any tone playing on the speaker. It is safe to call this when
+    /// nothing is playing.
+    pub fn stop_tone(&mut self) -> Result<(), CpError> {
+        self.send_cp_command(CP_NO_TONE, &[])
+    }
+
+    /// Plays `note` on the speaker for `duration_ms` milliseconds. Playing a
+    /// `Note::Rest` stops the speaker instead.
+    pub fn play_note(&mut self, note: Note, duration_ms: u16) -> Result<(), CpError> {
+        match note {
+            Note::Rest => self.stop_tone(),
+            _ => self.play_tone(note.frequency(), duration_ms),
+        }
+    }
+
+    /// Plays each `(note, duration_ms)` pair in order, waiting for each note
+    /// to finish before starting the next. If sending a note fails the
+    /// melody stops there and the error is returned.
+    pub fn play_melody(&mut self, notes: &[(Note, u16)]) -> Result<(), CpError> {
+        for &(note, duration_ms) in notes {
+            self.play_note(note, duration_ms)?;
+            thread::sleep(Duration::from_millis(duration_ms as u64));
+        }
+        Ok(())
+    }
+
+    /// Queues `note` to play from `start_ms` for `duration_ms` milliseconds,
+    /// without blocking. Nothing is played until `tick` is called with the
+    /// time, so the times can be on any clock as long as `tick` uses the
+    /// same one. The speaker plays one note at a time, so where notes
+    /// overlap the one that started last is heard.
+    pub fn schedule_note(&mut self, note: Note, start_ms: u64, duration_ms: u64) {
+        self.scheduled_notes.push(ScheduledNote {
+            note,
+            start_ms,
+            end_ms: start_ms.saturating_add(duration_ms),
+        });
+    }
+
+    /// Starts and stops the notes queued by `schedule_note` for the time
+    /// `now_ms`. Call it often from the main loop, alongside reading the
+    /// sensors, to play a melody without blocking; notes start and stop up
+    /// to one loop late. Returns true while queued notes are left to play.
+    pub fn tick(&mut self, now_ms: u64) -> Result<bool, CpError> {
+        self.scheduled_notes.retain(|scheduled| scheduled.end_ms > now_ms);
+
+        let current = self.scheduled_notes.iter()
+            .filter(|scheduled| scheduled.start_ms <= now_ms)
+            .max_by_key(|scheduled| scheduled.start_ms)
+            .copied();
+
+        if current != self.sounding_note {
+            match current {
+                Some(scheduled) if scheduled.note != Note::Rest => self.start_tone(scheduled.note.frequency())?,
+                _ => self.stop_tone()?,
+            }
+            self.sounding_note = current;
+        }
+        Ok(!self.scheduled_notes.is_empty())
+    }
+
+    /// Like `play_melody`, but with note lengths given as note values played
+    /// at a tempo of `bpm` quarter notes per minute. A note that would last
+    /// longer than 16383 ms stops the melody with `CpError::InvalidDuration`.
+    pub fn play_melody_bpm(&mut self, notes: &[(Note, NoteValue)], bpm: u16) -> Result<(), CpError> {
+        let notes: Vec<(Note, u16)> = notes.iter()
+            .map(|&(note, value)| (note, value.duration_ms(bpm).min(u16::MAX as u32) as u16))
+            .collect();
+
+        self.play_melody(&notes)
+    }
+
+    /// Reads the accelerometer and returns the x, y and z acceleration in
+    /// meters/second^2. Returns `CpError::Timeout` if the board doesn't reply.
+    pub fn read_accelerometer(&mut self) -> Result<(f32, f32, f32), CpError> {
+        self.discard_input()?;
+        self.send_cp_command(CP_ACCEL_READ, &[])?;
+
+        let data = self.wait_for(CP_ACCEL_READ_REPLY, self.reply_timeout)?;
+
+        match CpEvent::from_reply(CP_ACCEL_READ_REPLY, &data) {
+            Some(CpEvent::Accel(x, y, z)) => Ok((x, y, z)),
+            _ => Err(CpError::MalformedReply(CP_ACCEL_READ_REPLY)),
+        }
+    }
+
+    /// Reads the accelerometer `samples` times and returns the average x, y
+    /// and z acceleration in meters/second^2, which is steadier than a
+    /// single reading. Each sample is a separate round trip to the board,
+    /// a few milliseconds over USB, so the delay grows with `samples` and
+    /// also lags behind fast movement. A `samples` of 0 is treated as 1.
+    pub fn read_accelerometer_avg(&mut self, samples: usize) -> Result<(f32, f32, f32), CpError> {
+        let samples = samples.max(1);
+        let (mut x_total, mut y_total, mut z_total) = (0.0, 0.0, 0.0);
+
+        for _ in 0..samples {
+            let (x, y, z) = self.read_accelerometer()?;
+            x_total += x;
+            y_total += y;
+            z_total += z;
+        }
+
+        let count = samples as f32;
+        Ok((x_total / count, y_total / count, z_total / count))
+    }
+
+    /// Asks the firmware for its implementation version, which can be used to
+    /// check that it supports a command before sending it. The version is
+    /// cached after the first query. Returns `CpError::Timeout` if the board
+    /// doesn't reply.
+    pub fn implementation_version(&mut self) -> Result<Version, CpError> {
+        if let Some(version) = self.version {
+            return Ok(version);
+        }
+
+        self.discard_input()?;
+        self.send_cp_command(CP_IMPL_VERS, &[])?;
+
+        let data = self.wait_for(CP_IMPL_VERS_REPLY, self.reply_timeout)?;
+
+        match CpEvent::from_reply(CP_IMPL_VERS_REPLY, &data) {
+            Some(CpEvent::Version(version)) => {
+                self.version = Some(version);
+                Ok(version)
+            }
+            _ => Err(CpError::MalformedReply(CP_IMPL_VERS_REPLY)),
+        }
+    }
+
+    /// Returns `CpError::VersionTooOld` if the firmware is older than
+    /// `major`.`minor`, so that commands it doesn't support fail clearly.
+    pub fn require_version(&mut self, major: u8, minor: u8) -> Result<(), CpError> {
+        let found = self.implementation_version()?;
+
+        if (found.major, found.minor) < (major, minor) {
+            return Err(CpError::VersionTooOld { required: (major, minor), found });
+        }
+        Ok(())
+    }
+
+    /// Sends `code` from the IR transmitter. Only the Circuit Playground
+    /// Express has IR hardware, and the stock Circuit Playground Firmata
+    /// firmware has no IR commands, so after checking the implementation
+    /// version this returns `CpError::Unsupported` on every board. Use
+    /// `send_raw` to drive a custom firmware that adds them.
+    pub fn ir_send(&mut self, code: u32) -> Result<(), CpError> {
+        let _ = code;
+        self.require_ir()
+    }
+
+    /// Returns the last code picked up by the IR receiver, or `None` if
+    /// nothing was received. Like `ir_send`, this returns
+    /// `CpError::Unsupported` after checking the implementation version, as
+    /// no firmware has IR commands yet.
+    pub fn ir_receive(&mut self) -> Result<Option<u32>, CpError> {
+        self.require_ir()?;
+        Ok(None)
+    }
+
+    // Checks that the firmware has IR commands. No implementation version
+    // does yet, so once the version has been read this always fails.
+    fn require_ir(&mut self) -> Result<(), CpError> {
+        self.implementation_version()?;
+        Err(CpError::Unsupported("IR"))
+    }
+
+    /// Sets the range of the accelerometer. A wider range also makes tap
+    /// detection less sensitive, so the tap threshold should be lowered when
+    /// the range is raised (see CP_ACCEL_TAP_CONFIG for suggested values).
+    pub fn set_accel_range(&mut self, range: AccelRange) -> Result<(), CpError> {
+        self.send_cp_command(CP_ACCEL_RANGE, &[range.to_byte()])?;
+        self.accel_range = Some(range);
+        Ok(())
+    }
+
+    /// Reads the tap state of the accelerometer. Returns `CpError::Timeout`
+    /// if the board doesn't reply.
+    pub fn read_tap(&mut self) -> Result<TapState, CpError> {
+        self.discard_input()?;
+        self.send_cp_command(CP_ACCEL_TAP, &[])?;
+
+        let data = self.wait_for(CP_ACCEL_TAP_REPLY, self.reply_timeout)?;
+
+        match CpEvent::from_reply(CP_ACCEL_TAP_REPLY, &data) {
+            Some(CpEvent::Tap(state)) => Ok(state),
+            _ => Err(CpError::MalformedReply(CP_ACCEL_TAP_REPLY)),
+        }
+    }
+
+    /// Configures tap detection. The higher the `threshold` the less
+    /// sensitive it is, and good values depend on the accelerometer range:
+    /// +/-16G = 5-10, +/-8G = 10-20, +/-4G = 20-40, +/-2G = 40-80. The
+    /// default is a threshold of 80 at +/-2G.
+    pub fn set_tap_config(&mut self, click_type: ClickType, threshold: u8) -> Result<(), CpError> {
+        let click_type_bytes = protocol::encode_7bit_byte(click_type.to_byte());
+        let threshold_bytes = protocol::encode_7bit_byte(threshold);
+
+        self.send_cp_command(CP_ACCEL_TAP_CONFIG, &[
+            click_type_bytes[0],
+            click_type_bytes[1],
+            threshold_bytes[0],
+            threshold_bytes[1],
+        ])?;
+        self.tap_config = Some((click_type, threshold));
+        Ok(())
+    }
+
+    /// Starts streaming accelerometer readings from the board. Each reading
+    /// is passed to the callback registered with `on_accel` when it is
+    /// picked up by `read_and_dispatch`. Don't call `read_accelerometer`
+    /// while streaming, as the two will consume each other's replies. The
+    /// stream stops when the returned guard is dropped.
+    pub fn start_accel_stream(&mut self) -> Result<StreamGuard<'_>, CpError> {
+        self.send_stream_command(CP_ACCEL_STREAM_ON, &[])?;
+        Ok(StreamGuard::new(self, SensorStream::Accel))
+    }
+
+    /// Stops streaming accelerometer readings from the board and removes the
+    /// callback, so readings that were already on their way are ignored.
+    pub fn stop_accel_stream(&mut self) -> Result<(), CpError> {
+        self.accel_callback = None;
+        self.send_stream_command(CP_ACCEL_STREAM_OFF, &[])
+    }
+
+    /// Registers `callback` to be called with the x, y and z acceleration of
+    /// every streamed accelerometer reading, replacing any earlier callback.
+    /// The callback must be `Send` so the board can be driven from a
+    /// background thread.
+    pub fn on_accel<F: FnMut(f32, f32, f32) + Send + 'static>(&mut self, callback: F) {
+        self.accel_callback = Some(Box::new(callback));
+    }
+
+    /// Starts streaming tap states from the board. Each state is passed to
+    /// the callback registered with `on_tap` when it is picked up by
+    /// `read_and_dispatch`. Don't call `read_tap` while streaming. The
+    /// stream stops when the returned guard is dropped.
+    pub fn start_tap_stream(&mut self) -> Result<StreamGuard<'_>, CpError> {
+        self.send_stream_command(CP_ACCEL_TAP_STREAM_ON, &[])?;
+        Ok(StreamGuard::new(self, SensorStream::Tap))
+    }
+
+    /// Stops streaming tap states from the board and removes the callback,
+    /// so states that were already on their way are ignored.
+    pub fn stop_tap_stream(&mut self) -> Result<(), CpError> {
+        self.tap_callback = None;
+        self.send_stream_command(CP_ACCEL_TAP_STREAM_OFF, &[])
+    }
+
+    /// Registers `callback` to be called with every streamed tap state,
+    /// replacing any earlier callback.
+    pub fn on_tap<F: FnMut(TapState) + Send + 'static>(&mut self, callback: F) {
+        self.tap_callback = Some(Box::new(callback));
+    }
+
+    /// Registers `callback` to be called with every tap gesture `detector`
+    /// recognizes in the tap stream, replacing any earlier tap callback.
+    pub fn on_tap_gesture<F>(&mut self, mut detector: TapGestureDetector, mut callback: F)
+        where F: FnMut(TapGesture) + Send + 'static {
+        self.on_tap(move |state| {
+            if let Some(gesture) = detector.update(state, Instant::now()) {
+                callback(gesture);
+            }
+        });
+    }
+
+    /// Starts streaming readings of the capacitive touch input `pin`. Each
+    /// reading is passed to the callback registered for that pin with
+    /// `on_cap` when it is picked up by `read_and_dispatch`. The stream stops
+    /// when the returned guard is dropped. Several pins can stream at once
+    /// by starting the next stream through the previous guard.
+    pub fn start_cap_stream(&mut self, pin: u8) -> Result<StreamGuard<'_>, CpError> {
+        check_cap_pin(pin)?;
+
+        self.send_stream_command(CP_CAP_ON, &[pin])?;
+        Ok(StreamGuard::new(self, SensorStream::Cap(pin)))
+    }
+
+    /// Stops streaming readings of the capacitive touch input `pin` and
+    /// removes its callback.
+    pub fn stop_cap_stream(&mut self, pin: u8) -> Result<(), CpError> {
+        check_cap_pin(pin)?;
+        self.cap_callbacks.remove(&pin);
+
+        self.send_stream_command(CP_CAP_OFF, &[pin])
+    }
+
+    /// Registers `callback` to be called with every streamed reading of the
+    /// capacitive touch input `pin`, replacing any earlier callback for it.
+    pub fn on_cap<F: FnMut(i32) + Send + 'static>(&mut self, pin: u8, callback: F) {
+        self.cap_callbacks.insert(pin, Box::new(callback));
+    }
+
+    /// Registers `callback` to be called with debounced touch events for the
+    /// capacitive touch input `pin`, using `debouncer` to filter its streamed
+    /// readings. Like `on_cap`, this replaces any earlier callback for the
+    /// pin and needs `start_cap_stream` to be called for readings to arrive.
+    pub fn on_touch<F>(&mut self, pin: u8, mut debouncer: TouchDebouncer, mut callback: F)
+        where F: FnMut(TouchEvent) + Send + 'static {
+        self.on_cap(pin, move |value| {
+            if let Some(event) = debouncer.update(value) {
+                callback(event);
+            }
+        });
+    }
+
+    /// Starts `streams` and reads them on a background thread, which sends
+    /// every reply from the board over a channel as a `CpEvent`. This takes
+    /// ownership of the `CircuitPlayground`, use `EventStream::stop` to get
+    /// it back. Button callbacks are still called from the thread.
+    pub fn event_stream(self, streams: &[SensorStream]) -> Result<EventStream, CpError> {
+        EventStream::spawn(self, streams)
+    }
+
+    /// Reads one message from the board and passes it to the matching
+    /// registered callback. Call this in a loop, usually on a background
+    /// thread, while streaming. Returns `Ok` without doing anything if no
+    /// message arrives within the reply timeout.
+    pub fn read_and_dispatch(&mut self) -> Result<(), CpError> {
+        let deadline = Instant::now() + self.reply_timeout;
+
+        let event = match self.next_event(deadline) {
+            Ok(event) => event,
+            Err(CpError::Timeout) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        match event {
+            CpEvent::Accel(x, y, z) => {
+                if let Some(ref mut callback) = self.accel_callback {
+                    callback(x, y, z);
+                }
+            }
+            CpEvent::Tap(state) => {
+                if let Some(ref mut callback) = self.tap_callback {
+                    callback(state);
+                }
+            }
+            CpEvent::Cap(pin, value) => {
+                if let Some(callback) = self.cap_callbacks.get_mut(&pin) {
+                    callback(value);
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    // Calls the callback of every button on `port` whose level has changed.
+    // The first report for a port only records the levels, as the button
+    // hasn't been pressed or released.
+    fn dispatch_buttons(&mut self, port: u8, value: u16) {
+        for (&pin, button) in self.button_callbacks.iter_mut() {
+            if pin / 8 != port {
+                continue;
+            }
+
+            let pressed = port_pin_level(value, pin);
+            let previous = button.pressed.replace(pressed);
+
+            if previous == Some(!pressed) {
+                (button.callback)(pressed);
+            }
+        }
+    }
+
+    /// Registers `callback` to be called with the new state of the left
+    /// button (A) whenever it is pressed or released, replacing any earlier
+    /// callback. Changes are picked up by `read_and_dispatch`.
+    pub fn on_button_a<F: FnMut(bool) + Send + 'static>(&mut self, callback: F) -> Result<(), CpError> {
+        self.on_button(BUTTON_A_PIN, Box::new(callback))
+    }
+
+    /// Like `on_button_a`, but for the right button (B).
+    pub fn on_button_b<F: FnMut(bool) + Send + 'static>(&mut self, callback: F) -> Result<(), CpError> {
+        self.on_button(BUTTON_B_PIN, Box::new(callback))
+    }
+
+    // Registers a button callback and turns on reporting for its port.
+    fn on_button(&mut self, pin: u8, callback: ButtonCallback) -> Result<(), CpError> {
+        self.button_callbacks.insert(pin, Button { callback, pressed: None });
+        self.board.set_pin_mode(pin as i32, INPUT)?;
+        self.board.report_digital((pin / 8) as i32, 1)?;
+        Ok(())
+    }
+
+    /// Reads the accelerometer and returns which way the board is facing or
+    /// tilted. When no axis is clearly dominant the last orientation is
+    /// returned again, so the result doesn't jitter near the boundaries.
+    pub fn read_orientation(&mut self) -> Result<Orientation, CpError> {
+        let (x, y, z) = self.read_accelerometer()?;
+
+        if let Some(orientation) = Orientation::from_acceleration(x, y, z, self.orientation_threshold) {
+            self.orientation = orientation;
+        }
+
+        Ok(self.orientation)
+    }
+
+    /// Sets the acceleration in meters/second^2 that an axis needs before
+    /// `read_orientation` reports a new orientation. Higher values need a
+    /// more decisive tilt. The default is 7.0.
+    pub fn set_orientation_threshold(&mut self, threshold: f32) {
+        self.orientation_threshold = threshold;
+    }
+
+    /// Reads the accelerometer and returns the pitch and roll of the board
+    /// in degrees, as computed by `pitch_roll`.
+    pub fn read_pitch_roll(&mut self) -> Result<(f32, f32), CpError> {
+        let (x, y, z) = self.read_accelerometer()?;
+        Ok(pitch_roll(x, y, z))
+    }
+
+    /// Makes the ring a spirit level: reads the accelerometer and lights the
+    /// NeoPixel on the lowest edge of the board, or every pixel if the board
+    /// is level. Call it in a loop for a live level. Returns the lit pixel.
+    /// This follows the Circuit Playground's ring, so it needs exactly ten
+    /// pixels, and returns `CpError::NotARing` on a board set up with any
+    /// other `pixel_count`.
+    pub fn bubble_level(&mut self) -> Result<Option<u8>, CpError> {
+        let mut frame = self.ring_frame()?;
+        let (x, y, _) = self.read_accelerometer()?;
+        let pixel = downhill_pixel(x, y);
+
+        match pixel {
+            Some(index) => frame[index as usize] = Color::GREEN,
+            None => frame = vec![Color::GREEN; frame.len()],
+        }
+
+        self.set_pixels(&frame)?;
+        Ok(pixel)
+    }
+
+    /// Rolls a marble around the ring: reads the accelerometer and moves the
+    /// marble one NeoPixel towards the lowest edge of the board, lighting
+    /// it with `color`. On a level board the marble stays where it is. Call
+    /// it in a loop, with a short sleep to set the speed, and tilt the board
+    /// to steer. Returns the pixel the marble is on. Like `bubble_level`,
+    /// this needs exactly ten pixels.
+    pub fn marble<C: Into<Color>>(&mut self, color: C) -> Result<u8, CpError> {
+        let mut frame = self.ring_frame()?;
+        let (x, y, _) = self.read_accelerometer()?;
+
+        if let Some(downhill) = downhill_pixel(x, y) {
+            self.marble = step_toward(self.marble, downhill);
+        }
+
+        frame[self.marble as usize] = color.into();
+
+        self.set_pixels(&frame)?;
+        Ok(self.marble)
+    }
+
+    /// Turns the ring into an arrow pointing downhill: reads the accelerometer
+    /// and lights the NeoPixel in the direction the board tilts most with
+    /// `color`, and the pixels either side of it dimmer the further round
+    /// they are, leaving the uphill side off. If the board is flatter than
+    /// the dead zone set with `set_tilt_dead_zone`, nothing is lit. Returns
+    /// the pixel the arrow points at. Like `bubble_level`, this needs exactly
+    /// ten pixels.
+    pub fn tilt_arrow<C: Into<Color>>(&mut self, color: C) -> Result<Option<u8>, CpError> {
+        let color = color.into();
+        let mut frame = self.ring_frame()?;
+        let (x, y, _) = self.read_accelerometer()?;
+
+        if (x * x + y * y).sqrt() < self.tilt_dead_zone {
+            self.set_pixels(&frame)?;
+            return Ok(None);
+        }
+
+        // Downhill is (-x, -y), as in downhill_pixel.
+        let angle = (-x).atan2(y).to_degrees().rem_euclid(360.0);
+        let head = pixel_at_angle(angle);
+
+        for (pixel, &pixel_angle) in frame.iter_mut().zip(PIXEL_ANGLES.iter()) {
+            let difference = (pixel_angle - angle).abs();
+            let distance = difference.min(360.0 - difference);
+
+            *pixel = color.scale(1.0 - distance / TILT_ARROW_SPREAD);
+        }
+        frame[head as usize] = color;
+
+        self.set_pixels(&frame)?;
+        Ok(Some(head))
+    }
+
+    /// Sets the sideways acceleration in meters/second^2 below which
+    /// `tilt_arrow` treats the board as flat. The default is
+    /// `TILT_DEAD_ZONE`, about 6 degrees of tilt.
+    pub fn set_tilt_dead_zone(&mut self, dead_zone: f32) {
+        self.tilt_dead_zone = dead_zone;
+    }
+
+    /// Samples the accelerometer for `window` and returns true if the board
+    /// was shaken harder than `threshold_g`. `SHAKE_GENTLE_G` and
+    /// `SHAKE_HARD_G` are good starting points for the threshold.
+    pub fn detect_shake(&mut self, threshold_g: f32, window: Duration) -> Result<bool, CpError> {
+        let end = Instant::now() + window;
+        let mut samples = vec![];
+
+        while Instant::now() < end {
+            samples.push(self.read_accelerometer()?);
+        }
+
+        Ok(is_shake(&samples, threshold_g, SHAKE_MIN_SAMPLES))
+    }
+
+    /// Reads the capacitive touch input `pin`, which must be one of
+    /// `CAP_PINS`. Returns `CpError::Timeout` if the board doesn't reply.
+    pub fn cap_read(&mut self, pin: u8) -> Result<i32, CpError> {
+        check_cap_pin(pin)?;
+
+        self.discard_input()?;
+        self.send_cp_command(CP_CAP_READ, &[pin])?;
+
+        let deadline = Instant::now() + self.reply_timeout;
+
+        // Streamed readings of other pins share the reply sub-command.
+        loop {
+            let data = self.wait_for(CP_CAP_REPLY, deadline.saturating_duration_since(Instant::now()))?;
+
+            match CpEvent::from_reply(CP_CAP_REPLY, &data) {
+                Some(CpEvent::Cap(reply_pin, value)) if reply_pin == pin => return Ok(value),
+                Some(_) => {}
+                None => return Err(CpError::MalformedReply(CP_CAP_REPLY)),
+            }
+        }
+    }
+
+    /// Reads every capacitive touch input in `CAP_PINS`, returning the
+    /// readings keyed by pin. The pins are read one after another, so this
+    /// takes up to eight reply round trips. Returns `CpError::Timeout` if the
+    /// board doesn't reply to one of them.
+    pub fn read_all_cap(&mut self) -> Result<BTreeMap<u8, i32>, CpError> {
+        let mut readings = BTreeMap::new();
+
+        for &pin in CAP_PINS.iter() {
+            readings.insert(pin, self.cap_read(pin)?);
+        }
+        Ok(readings)
+    }
+
+    /// Returns true if the capacitive touch input `pin` reads above the cap
+    /// threshold. The untouched reading drifts with humidity and what the
+    /// board is sitting on, so the threshold may need tuning with
+    /// `set_cap_threshold`, or the pin calibrated with `calibrate_cap`. Once
+    /// calibrated, the reading has to be the threshold above the baseline.
+    pub fn is_touched(&mut self, pin: u8) -> Result<bool, CpError> {
+        let value = self.cap_read(pin)?;
+        Ok(self.cap_value_touched(pin, value))
+    }
+
+    // Returns true if `value` read from the cap touch input `pin` counts as
+    // touched, going by the threshold and the pin's baseline.
+    fn cap_value_touched(&self, pin: u8, value: i32) -> bool {
+        let baseline = self.cap_baselines.get(&pin).map_or(0, |&baseline| baseline as i64);
+
+        value > 0 && value as i64 - baseline > self.cap_threshold as i64
+    }
+
+    /// Reads every capacitive touch pad and lights the NeoPixel next to each
+    /// touched one, as mapped by `CAP_PAD_PIXELS`, in a color of its own.
+    /// Call it in a loop for a live display. Touches are judged as by
+    /// `is_touched`. Returns the touched pads. This follows the Circuit
+    /// Playground's ring, so like `bubble_level` it needs exactly ten pixels.
+    pub fn touch_pixels(&mut self) -> Result<Vec<u8>, CpError> {
+        let mut frame = self.ring_frame()?;
+        let readings = self.read_all_cap()?;
+        let mut touched = vec![];
+
+        for (index, &(pad, pixel)) in CAP_PAD_PIXELS.iter().enumerate() {
+            if self.cap_value_touched(pad, readings[&pad]) {
+                frame[pixel as usize] = color_wheel((index * 256 / CAP_PAD_PIXELS.len()) as u8);
+                touched.push(pad);
+            }
+        }
+
+        self.set_pixels(&frame)?;
+        touched.sort_unstable();
+        Ok(touched)
+    }
+
+    /// Averages `samples` readings of the capacitive touch input `pin`, which
+    /// must not be touched meanwhile, and stores the result as its baseline
+    /// for `is_touched`. Returns the baseline.
+    pub fn calibrate_cap(&mut self, pin: u8, samples: u32) -> Result<i32, CpError> {
+        let samples = samples.max(1);
+        let mut total: i64 = 0;
+
+        for _ in 0..samples {
+            total += self.cap_read(pin)? as i64;
+        }
+
+        let baseline = (total / samples as i64) as i32;
+        self.cap_baselines.insert(pin, baseline);
+        Ok(baseline)
+    }
+
+    /// Sets the value a cap touch input must read above to count as touched.
+    /// The default is `CAP_THRESHOLD`.
+    pub fn set_cap_threshold(&mut self, threshold: u64) {
+        self.cap_threshold = threshold;
+    }
+
+    /// Senses the color of whatever is held over the light sensor. The board
+    /// lights a NeoPixel and measures the reflected light, so bright
+    /// ambient light will skew the result. Returns `CpError::Timeout` if the
+    /// board doesn't reply.
+    pub fn sense_color(&mut self) -> Result<Color, CpError> {
+        self.discard_input()?;
+        self.send_cp_command(CP_SENSECOLOR, &[])?;
+
+        let data = self.wait_for(CP_SENSECOLOR_REPLY, self.reply_timeout)?;
+
+        match CpEvent::from_reply(CP_SENSECOLOR_REPLY, &data) {
+            Some(CpEvent::Color(color)) => Ok(color),
+            _ => Err(CpError::MalformedReply(CP_SENSECOLOR_REPLY)),
+        }
+    }
+
+    /// Reads the thermistor and returns the board's temperature in degrees
+    /// Celsius. Returns `CpError::Timeout` if the board doesn't reply.
+    pub fn read_temperature_c(&mut self) -> Result<f64, CpError> {
+        let value = self.read_analog_channel(THERM_PIN)?;
+        Ok(therm_value_to_celsius(value))
+    }
+
+    /// Reads the thermistor and returns the board's temperature in degrees
+    /// Fahrenheit.
+    pub fn read_temperature_f(&mut self) -> Result<f64, CpError> {
+        Ok(self.read_temperature()?.fahrenheit())
+    }
+
+    /// Reads the thermistor and returns the board's temperature in Kelvin.
+    pub fn read_temperature_k(&mut self) -> Result<f64, CpError> {
+        Ok(self.read_temperature()?.kelvin())
+    }
+
+    /// Reads the thermistor and returns the board's temperature, which can
+    /// then be converted to any unit.
+    pub fn read_temperature(&mut self) -> Result<Temperature, CpError> {
+        Ok(Temperature::from_celsius(self.read_temperature_c()?))
+    }
+
+    /// Reads the temperature, light, sound and accelerometer one after the
+    /// other, for logging them together.
+    pub fn snapshot(&mut self) -> Result<Snapshot, CpError> {
+        Ok(Snapshot {
+            temperature_c: self.read_temperature_c()?,
+            light: self.read_light_level()?,
+            sound: self.read_sound_peak(SNAPSHOT_SOUND_SAMPLES)?,
+            accel: self.read_accelerometer()?,
+        })
+    }
+
+    /// Checks each part of the board in turn: cycles the ring through red,
+    /// green and blue, plays a short tone, reads the accelerometer,
+    /// thermistor and light sensor, and asks for the firmware's
+    /// implementation version. The board should be lying still for the
+    /// accelerometer to pass. A part that doesn't reply fails the test
+    /// rather than returning `CpError::Timeout`, but errors from the
+    /// connection itself are still returned.
+    pub fn self_test(&mut self) -> Result<SelfTestReport, CpError> {
+        let mut pixels = true;
+        for &color in [Color::RED, Color::GREEN, Color::BLUE].iter() {
+            pixels &= self_test_step(self.fill(color), |_| true)?;
+            thread::sleep(Duration::from_millis(SELF_TEST_PIXEL_MS));
+        }
+        pixels &= self_test_step(self.clear_and_show(), |_| true)?;
+
+        let speaker = self_test_step(self.play_tone(SELF_TEST_TONE_HZ, SELF_TEST_TONE_MS), |_| true)?;
+
+        let accelerometer = self_test_step(self.read_accelerometer(), |(x, y, z)| {
+            let g = (x * x + y * y + z * z).sqrt() / GRAVITY;
+            (SELF_TEST_ACCEL_MIN_G..=SELF_TEST_ACCEL_MAX_G).contains(&g)
+        })?;
+
+        let temperature = self_test_step(self.read_temperature_c(), |celsius| {
+            (SELF_TEST_TEMPERATURE_MIN_C..=SELF_TEST_TEMPERATURE_MAX_C).contains(&celsius)
+        })?;
+
+        let light = self_test_step(self.read_light_level(), |level| level <= 1023)?;
+
+        // Ask again rather than trusting a version cached earlier.
+        self.version = None;
+        let firmware = self_test_step(self.implementation_version(), |_| true)?;
+
+        Ok(SelfTestReport { pixels, speaker, accelerometer, temperature, light, firmware })
+    }
+
+    /// Moves the board onto a background thread that reads the thermistor
+    /// every `interval` and passes the temperature in degrees Celsius to
+    /// `callback`, for alarms such as beeping when it gets too hot. Use the
+    /// returned `TemperatureMonitor` to stop it and get the board back. A
+    /// reading the board doesn't reply to in time is skipped, but any other
+    /// error stops the thread and is returned by `TemperatureMonitor::stop`.
+    pub fn on_temperature<F>(self, interval: Duration, callback: F) -> TemperatureMonitor
+        where F: FnMut(f64) + Send + 'static {
+        TemperatureMonitor::spawn(self, interval, callback)
+    }
+
+    /// Reads the thermistor and fills the ring with `Color::from_temperature`
+    /// for it, going from blue at 15C to red at 35C. Returns the temperature
+    /// in degrees Celsius.
+    pub fn show_temperature(&mut self) -> Result<f64, CpError> {
+        let celsius = self.read_temperature_c()?;
+
+        self.fill(Color::from_temperature(celsius, SHOW_TEMPERATURE_MIN_C, SHOW_TEMPERATURE_MAX_C))?;
+        Ok(celsius)
+    }
+
+    /// Runs the ring as a night light: reads the light sensor every 100 ms
+    /// and fills the ring with `color` at a gentle brightness when the raw
+    /// light level drops below `on_threshold`, turning it off again once it
+    /// brightens, as decided by `night_light_lit`. This never returns unless
+    /// talking to the board fails; use `night_light_until` to stop it.
+    pub fn night_light<C: Into<Color>>(&mut self, color: C, on_threshold: u16) -> Result<(), CpError> {
+        self.night_light_until(color, on_threshold, || false)
+    }
+
+    /// Like `night_light`, but keeps going until `stop` returns true. It is
+    /// called before every reading, so callers can break out at any time.
+    /// The ring is turned off when it stops.
+    pub fn night_light_until<C, F>(&mut self, color: C, on_threshold: u16, mut stop: F) -> Result<(), CpError>
+        where C: Into<Color>, F: FnMut() -> bool {
+        let color = color.into();
+        let mut lit = false;
+
+        self.clear_and_show()?;
+
+        while !stop() {
+            let level = self.read_light_level()?;
+            let now_lit = night_light_lit(lit, level, on_threshold);
+
+            if now_lit && !lit {
+                self.set_brightness(NIGHT_LIGHT_BRIGHTNESS)?;
+                self.fill(color)?;
+            } else if lit && !now_lit {
+                self.clear_and_show()?;
+            }
+
+            lit = now_lit;
+            thread::sleep(Duration::from_millis(NIGHT_LIGHT_POLL_MS));
+        }
+
+        self.clear_and_show()
+    }
+
+    /// Reads the light sensor and returns the raw value, from 0 (dark) to
+    /// 1023 (bright).
+    pub fn read_light_level(&mut self) -> Result<u16, CpError> {
+        self.read_analog_channel(LIGHT_PIN)
+    }
+
+    /// Reads the light sensor and returns the level from 0.0 (dark) to 1.0
+    /// (bright).
+    pub fn read_light_fraction(&mut self) -> Result<f64, CpError> {
+        Ok(map_range(self.read_light_level()? as f64, 0.0, 1023.0, 0.0, 1.0))
+    }
+
+    /// Reads the light sensor and returns the average of the last `window`
+    /// raw readings taken by this method, which is steadier than a single
+    /// reading. The first `window` calls are still warming up. Changing
+    /// `window` starts the average over.
+    pub fn read_light_smoothed(&mut self, window: usize) -> Result<f64, CpError> {
+        let reading = self.read_light_level()?;
+
+        let smoother = self.light_smoother.get_or_insert_with(|| LightSmoother::new(window));
+        if smoother.window() != window.max(1) {
+            *smoother = LightSmoother::new(window);
+        }
+
+        Ok(smoother.update(reading))
+    }
+
+    /// Reads a single raw sample, from 0 to 1023, from the microphone. A
+    /// single sample says little about how loud it is, so `read_sound_peak`
+    /// is usually more useful.
+    pub fn read_sound_level(&mut self) -> Result<u16, CpError> {
+        self.read_analog_channel(SOUND_PIN)
+    }
+
+    /// Reads `samples` samples from the microphone and returns their
+    /// peak-to-peak amplitude, which rises with loudness. Samples arrive at
+    /// the Firmata sampling interval (about 50 per second by default), far
+    /// too slowly to capture the sound wave itself, so treat this as a rough
+    /// loudness level.
+    pub fn read_sound_peak(&mut self, samples: usize) -> Result<u16, CpError> {
+        if samples == 0 {
+            return Ok(0);
+        }
+
+        Ok(peak_to_peak(&self.read_analog_samples(SOUND_PIN, samples)?))
+    }
+
+    /// Listens to the microphone for up to `timeout` and returns true as soon
+    /// as it hears a clap, a sudden jump in loudness above `threshold`, as
+    /// checked by `is_clap`. Returns false if there was no clap in time.
+    pub fn detect_clap(&mut self, threshold: u16, timeout: Duration) -> Result<bool, CpError> {
+        let end = Instant::now() + timeout;
+        let mut previous_peak = self.read_sound_peak(CLAP_SAMPLES)?;
+
+        while Instant::now() < end {
+            let peak = self.read_sound_peak(CLAP_SAMPLES)?;
+            if is_clap(previous_peak, peak, threshold) {
+                return Ok(true);
+            }
+            previous_peak = peak;
+        }
+        Ok(false)
+    }
+
+    /// Returns true if the left button (A) is pressed. The buttons have
+    /// pull-down resistors on the board, so they read high when pressed.
+    pub fn button_a(&mut self) -> Result<bool, CpError> {
+        self.read_digital_pin(BUTTON_A_PIN, INPUT)
+    }
+
+    /// Returns true if the right button (B) is pressed.
+    pub fn button_b(&mut self) -> Result<bool, CpError> {
+        self.read_digital_pin(BUTTON_B_PIN, INPUT)
+    }
+
+    /// Returns whether the left (A) and right (B) buttons are pressed, read
+    /// together. The buttons are on different digital ports, so reporting
+    /// is turned on for both and their next reports are waited for
+    /// together, taking one round trip instead of the two that `button_a`
+    /// and `button_b` take, and turned back off afterwards. Returns
+    /// `CpError::Timeout` if either port doesn't report.
+    pub fn buttons_pressed(&mut self) -> Result<(bool, bool), CpError> {
+        let (port_a, port_b) = (BUTTON_A_PIN / 8, BUTTON_B_PIN / 8);
+
+        self.input.clear();
+        for &(pin, port) in [(BUTTON_A_PIN, port_a), (BUTTON_B_PIN, port_b)].iter() {
+            self.board.set_pin_mode(pin as i32, INPUT)?;
+            self.board.report_digital(port as i32, 1)?;
+        }
+
+        let deadline = Instant::now() + self.reply_timeout;
+        let result = self.read_both_buttons(deadline);
+
+        self.stop_digital_reporting(port_a)?;
+        self.stop_digital_reporting(port_b)?;
+        result
+    }
+
+    // Reads until both button ports have reported and returns the levels of
+    // the buttons.
+    fn read_both_buttons(&mut self, deadline: Instant) -> Result<(bool, bool), CpError> {
+        let (port_a, port_b) = (BUTTON_A_PIN / 8, BUTTON_B_PIN / 8);
+        let (mut a, mut b) = (None, None);
+
+        while a.is_none() || b.is_none() {
+            if let Message::Digital(port, value) = self.read_message(deadline)? {
+                if port == port_a {
+                    a = Some(port_pin_level(value, BUTTON_A_PIN));
+                }
+                if port == port_b {
+                    b = Some(port_pin_level(value, BUTTON_B_PIN));
+                }
+            }
+        }
+
+        Ok((a == Some(true), b == Some(true)))
+    }
+
+    /// Returns true if both buttons are pressed at once, for chords such as
+    /// a reset gesture.
+    pub fn both_pressed(&mut self) -> Result<bool, CpError> {
+        let (a, b) = self.buttons_pressed()?;
+        Ok(a && b)
+    }
+
+    /// Returns the position of the slide switch. The switch connects its pin
+    /// to ground on the right, so its pin is read with the pull-up enabled
+    /// and reads high on the left.
+    pub fn slide_switch(&mut self) -> Result<SwitchPosition, CpError> {
+        if self.read_digital_pin(SLIDE_SWITCH_PIN, INPUT_PULLUP)? {
+            Ok(SwitchPosition::Left)
+        } else {
+            Ok(SwitchPosition::Right)
+        }
+    }
+
+    /// Turns the small red LED next to the USB port on or off. It is separate
+    /// from the NeoPixels and makes a low-power status light.
+    pub fn set_red_led(&mut self, on: bool) -> Result<(), CpError> {
+        self.set_pin_mode(RED_LED_PIN, OUTPUT)?;
+        self.digital_write(RED_LED_PIN, on)
+    }
+
+    /// Sets the Firmata `mode` of `pin`, such as `INPUT`, `OUTPUT`, `PWM` or
+    /// `INPUT_PULLUP`, so pads without an onboard peripheral can be used.
+    pub fn set_pin_mode(&mut self, pin: u8, mode: u8) -> Result<(), CpError> {
+        self.check_pin(pin)?;
+
+        self.board.set_pin_mode(pin as i32, mode)?;
+        Ok(())
+    }
+
+    /// Drives `pin` high or low. The pin should be set to `OUTPUT` first.
+    pub fn digital_write(&mut self, pin: u8, value: bool) -> Result<(), CpError> {
+        // Firmata writes a whole port at once, so every pin on it has to exist.
+        if self.check_pin(pin | 0x07).is_err() {
+            return Err(CpError::InvalidPin(pin));
+        }
+
+        self.board.digital_write(pin as i32, value as i32)?;
+        Ok(())
+    }
+
+    /// Writes `level` to `pin`, such as a PWM duty cycle from 0 to 255. The
+    /// pin should be set to `PWM` first. Firmata analog messages only
+    /// address pins 0-15.
+    pub fn analog_write(&mut self, pin: u8, level: u16) -> Result<(), CpError> {
+        if pin > 15 {
+            return Err(CpError::InvalidPin(pin));
+        }
+        self.check_pin(pin)?;
+
+        self.board.analog_write(pin as i32, level as i32)?;
+        Ok(())
+    }
+
+    /// Reads the 10-bit value of the analog input `channel`, for example 7
+    /// for A7. Firmata only reports channels 0-15.
+    pub fn analog_read(&mut self, channel: u8) -> Result<u16, CpError> {
+        if channel > 15 {
+            return Err(CpError::InvalidPin(channel));
+        }
+
+        self.read_analog_channel(channel)
+    }
+
+    // Returns `CpError::InvalidPixel` unless `index` is one of the NeoPixels.
+    fn check_pixel(&self, index: u8) -> Result<(), CpError> {
+        if index >= self.pixel_count {
+            return Err(CpError::InvalidPixel(index));
+        }
+        Ok(())
+    }
+
+    // Returns `CpError::InvalidPin` unless the board reported having `pin`.
+    fn check_pin(&self, pin: u8) -> Result<(), CpError> {
+        if pin as usize >= self.board.pins.len() {
+            return Err(CpError::InvalidPin(pin));
+        }
+        Ok(())
+    }
+
+    // Sets `pin` to the input `mode` and returns its level. The board reports
+    // the whole port the pin belongs to whenever reporting for it is turned
+    // on, so it is turned on for the read and back off afterwards.
+    fn read_digital_pin(&mut self, pin: u8, mode: u8) -> Result<bool, CpError> {
+        let port = pin / 8;
+
+        self.input.clear();
+        self.board.set_pin_mode(pin as i32, mode)?;
+        self.board.report_digital(port as i32, 1)?;
+
+        let deadline = Instant::now() + self.reply_timeout;
+        let result = self.read_digital_port(port, deadline);
+
+        self.stop_digital_reporting(port)?;
+        result.map(|value| port_pin_level(value, pin))
+    }
+
+    // Reads until a digital message for `port` arrives and returns its value.
+    fn read_digital_port(&mut self, port: u8, deadline: Instant) -> Result<u16, CpError> {
+        loop {
+            if let Message::Digital(message_port, value) = self.read_message(deadline)? {
+                if message_port == port {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+
+    // Turns reporting for `port` back off after a one-shot read, unless a
+    // button callback still needs it.
+    fn stop_digital_reporting(&mut self, port: u8) -> Result<(), CpError> {
+        if self.button_callbacks.keys().any(|pin| pin / 8 == port) {
+            return Ok(());
+        }
+        self.board.report_digital(port as i32, 0)?;
+        Ok(())
+    }
+
+    // Turns on reporting for the analog input `channel`, waits for the next
+    // value it reports and turns reporting back off.
+    fn read_analog_channel(&mut self, channel: u8) -> Result<u16, CpError> {
+        Ok(self.read_analog_samples(channel, 1)?[0])
+    }
+
+    // Like read_analog_channel, but collects the next `count` values that
+    // `channel` reports.
+    fn read_analog_samples(&mut self, channel: u8, count: usize) -> Result<Vec<u16>, CpError> {
+        self.input.clear();
+        self.board.report_analog(channel as i32, 1)?;
+
+        let mut samples = Vec::with_capacity(count);
+        let mut result = Ok(());
+
+        while samples.len() < count {
+            let deadline = Instant::now() + self.reply_timeout;
+
+            match self.read_analog_value(channel, deadline) {
+                Ok(value) => samples.push(value),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        self.board.report_analog(channel as i32, 0)?;
+        result.map(|_| samples)
+    }
+
+    // Reads until an analog message for `channel` arrives and returns its value.
+    fn read_analog_value(&mut self, channel: u8, deadline: Instant) -> Result<u16, CpError> {
+        loop {
+            if let Message::Analog(message_channel, value) = self.read_message(deadline)? {
+                if message_channel == channel {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+
+    /// Turns everything off at once: stops every stream, the speaker and
+    /// any notes queued by `schedule_note`, and turns off the NeoPixels.
+    /// This is what dropping the `CircuitPlayground` does, but errors are
+    /// returned instead of ignored, for error recovery and shutdown paths.
+    /// Every command is tried even if an earlier one fails, and the first
+    /// error is returned.
+    pub fn stop_all(&mut self) -> Result<(), CpError> {
+        self.scheduled_notes.clear();
+        self.sounding_note = None;
+
+        let mut results = vec![self.stop_accel_stream(), self.stop_tap_stream()];
+        for &pin in CAP_PINS.iter() {
+            results.push(self.stop_cap_stream(pin));
+        }
+        results.push(self.stop_tone());
+        results.push(self.clear_and_show());
+
+        results.into_iter().collect()
+    }
+
+    /// Resets the firmware to its power-on state and waits until it answers
+    /// a firmware version query, so the next command is sure to be heard.
+    /// Reporting for ports with button callbacks is turned back on. Returns
+    /// `CpError::Timeout` if the firmware doesn't answer.
+    pub fn reset(&mut self) -> Result<(), CpError> {
+        self.input.clear();
+        self.version = None;
+        self.clear_tracked_pixels();
+        self.write_with_retry(&[SYSTEM_RESET])?;
+        self.write_with_retry(&[START_SYSEX, REPORT_FIRMWARE, END_SYSEX])?;
+
+        let deadline = Instant::now() + self.reply_timeout;
+
+        loop {
+            if let Message::Sysex(frame) = self.read_message(deadline)? {
+                if frame.first() == Some(&REPORT_FIRMWARE) {
+                    break;
+                }
+            }
+        }
+
+        let mut ports: Vec<u8> = self.button_callbacks.keys().map(|pin| pin / 8).collect();
+        ports.sort();
+        ports.dedup();
+        for port in ports {
+            self.board.report_digital(port as i32, 1)?;
+        }
+        Ok(())
+    }
+
+    /// Reads and throws away everything the board has already sent, for up to
+    /// `DRAIN_TIMEOUT_MS` if it keeps sending, so that the next reply isn't
+    /// mixed up with stale stream readings. Methods that wait for a reply
+    /// already throw away bytes left over from earlier reads, and drain
+    /// first by themselves after a stream has been started or stopped, so
+    /// this is rarely needed.
+    pub fn drain_input(&mut self) -> Result<(), CpError> {
+        let deadline = Instant::now() + Duration::from_millis(DRAIN_TIMEOUT_MS);
+
+        while Instant::now() < deadline && self.fill_input()? {}
+
+        self.input.clear();
+        Ok(())
+    }
+
+    // Throws away input left over from earlier reads before sending a query
+    // whose reply is waited for. After a stream has been started or stopped
+    // it is drained too; otherwise that is skipped, since an empty serial
+    // port makes every drain wait out a read timeout.
+    fn discard_input(&mut self) -> Result<(), CpError> {
+        if self.streamed {
+            self.streamed = false;
+            return self.drain_input();
+        }
+
+        self.input.clear();
+        Ok(())
+    }
+
+    /// Sends the Circuit Playground sub-command `sub_command` with `data`,
+    /// framed like every built-in command, for experimenting with firmware
+    /// commands this crate doesn't wrap. Sysex data can only carry 7 bits per
+    /// byte, so the top bit of every byte is dropped. Encode 8-bit values
+    /// with the functions in `protocol` first.
+    pub fn send_raw(&mut self, sub_command: u8, data: &[u8]) -> Result<(), CpError> {
+        self.send_cp_command(sub_command & 0x7F, data)
+    }
+
+    // Sends the Circuit Playground sub-command `sub` with `data` to the
+    // board. Every command other than those in a `Batch` goes through here,
+    // so it's framed on the stack to avoid allocating in animation loops.
+    fn send_cp_command(&mut self, sub: u8, data: &[u8]) -> Result<(), CpError> {
+        if data.len() > CP_COMMAND_STACK_DATA_LEN {
+            let mut buf = Vec::with_capacity(data.len() + CP_COMMAND_FRAMING_LEN);
+            frame_cp_command(&mut buf, sub, data);
+
+            return self.write_with_retry(&buf);
+        }
+
+        let mut buf = [0; CP_COMMAND_STACK_DATA_LEN + CP_COMMAND_FRAMING_LEN];
+        let len = write_cp_command(&mut buf, sub, data);
+
+        self.write_with_retry(&buf[..len])
+    }
+
+    // Writes all of `buf` to the board, trying again up to `write_retries`
+    // times, after `WRITE_RETRY_DELAY_MS`, when a write fails with
+    // `WouldBlock` or `Interrupted`. Bytes already written aren't sent
+    // twice, so a command is never doubled up.
+    fn write_with_retry(&mut self, buf: &[u8]) -> Result<(), CpError> {
+        let mut written = 0;
+        let mut retries = 0;
+
+        while written < buf.len() {
+            match self.board.connection.write(&buf[written..]) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero).into()),
+                Ok(count) => written += count,
+                Err(ref err) if is_transient(err) && retries < self.write_retries => {
+                    retries += 1;
+                    thread::sleep(Duration::from_millis(WRITE_RETRY_DELAY_MS));
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+
+    // Sends a command that starts or stops a stream, noting that there may
+    // be readings on their way for the next query to drain.
+    fn send_stream_command(&mut self, sub: u8, data: &[u8]) -> Result<(), CpError> {
+        self.streamed = true;
+        self.send_cp_command(sub, data)
+    }
+
+    // Returns the next Circuit Playground reply from the board, or `None` if
+    // no complete reply has arrived yet. Bytes are kept between calls, so a
+    // reply split across several reads is put back together. Digital port
+    // messages read along the way are passed to the button callbacks.
+    fn poll(&mut self) -> Result<Option<CpEvent>, CpError> {
+        loop {
+            let message = match self.take_message() {
+                Some(message) => message,
+                None => {
+                    if !self.fill_input()? {
+                        return Ok(None);
+                    }
+                    continue;
+                }
+            };
+
+            match message {
+                Message::Sysex(frame) => {
+                    if let Some(event) = CpEvent::from_sysex(&frame) {
+                        return Ok(Some(event));
+                    }
+                }
+                Message::Digital(port, value) => self.dispatch_buttons(port, value),
+                Message::Analog(..) => {}
+            }
+        }
+    }
+
+    // Reads until a Circuit Playground reply with the sub-command `reply_sub`
+    // arrives and returns its data, returning `CpError::Timeout` if none
+    // does within `timeout`. Other replies, such as streamed readings, are
+    // thrown away, and digital port messages are passed to the button
+    // callbacks.
+    fn wait_for(&mut self, reply_sub: u8, timeout: Duration) -> Result<Vec<u8>, CpError> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            match self.read_message(deadline)? {
+                Message::Sysex(frame) => {
+                    if frame.len() >= 2 && frame[0] == CP_COMMAND && frame[1] == reply_sub {
+                        return Ok(frame[2..].to_vec());
+                    }
+                }
+                Message::Digital(port, value) => self.dispatch_buttons(port, value),
+                Message::Analog(..) => {}
+            }
+        }
+    }
+
+    // Polls until a Circuit Playground reply arrives, returning
+    // `CpError::Timeout` if none does before `deadline`.
+    fn next_event(&mut self, deadline: Instant) -> Result<CpEvent, CpError> {
+        loop {
+            if let Some(event) = self.poll()? {
+                return Ok(event);
+            }
+            if Instant::now() > deadline {
+                return Err(CpError::Timeout);
+            }
+        }
+    }
+
+    // Reads the next complete message of any kind from the board, returning
+    // `CpError::Timeout` if none arrives before `deadline`.
+    fn read_message(&mut self, deadline: Instant) -> Result<Message, CpError> {
+        loop {
+            if let Some(message) = self.take_message() {
+                return Ok(message);
+            }
+            if Instant::now() > deadline {
+                return Err(CpError::Timeout);
+            }
+            self.fill_input()?;
+        }
+    }
+
+    // Removes the first complete message from the input buffer. Bytes that
+    // don't belong to a message this crate understands are dropped, and an
+    // incomplete message is left in place until the rest of it arrives.
+    fn take_message(&mut self) -> Option<Message> {
+        loop {
+            let start = self.input.iter().position(|&byte| {
+                byte == START_SYSEX
+                    || byte & 0xF0 == DIGITAL_MESSAGE
+                    || byte & 0xF0 == ANALOG_MESSAGE
+            });
+
+            match start {
+                Some(start) => { self.input.drain(..start); }
+                None => {
+                    self.input.clear();
+                    return None;
+                }
+            }
+
+            let status = self.input[0];
+            // The position of the first byte after the status byte that
+            // starts another message, if any.
+            let next = self.input[1..].iter().position(|&byte| byte & 0x80 != 0).map(|i| i + 1);
+
+            if status == START_SYSEX {
+                match next {
+                    Some(end) if self.input[end] == END_SYSEX => {
+                        let frame = self.input[1..end].to_vec();
+                        self.input.drain(..=end);
+                        return Some(Message::Sysex(frame));
+                    }
+                    // Another message started before this one ended, so
+                    // the rest of this one was lost.
+                    Some(end) => { self.input.drain(..end); }
+                    None => return None,
+                }
+            } else {
+                match next {
+                    Some(end) if end < 3 => { self.input.drain(..end); }
+                    _ if self.input.len() < 3 => return None,
+                    _ => {
+                        let value = (self.input[1] as u16) | ((self.input[2] as u16) << 7);
+                        self.input.drain(..3);
+
+                        if status & 0xF0 == DIGITAL_MESSAGE {
+                            return Some(Message::Digital(status & 0x0F, value));
+                        }
+                        return Some(Message::Analog(status & 0x0F, value));
+                    }
+                }
+            }
+        }
+    }
+
+    // Reads whatever the board has sent into the input buffer, returning
+    // false if nothing arrived.
+    fn fill_input(&mut self) -> Result<bool, CpError> {
+        let mut buf = [0u8; 64];
+
+        match self.board.connection.read(&mut buf) {
+            Ok(0) => {
+                thread::sleep(Duration::from_millis(1));
+                Ok(false)
+            }
+            Ok(len) => {
+                self.input.extend_from_slice(&buf[..len]);
+                Ok(true)
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => Ok(false),
+            Err(e) => Err(CpError::Io(e)),
+        }
+    }
+}
+
+impl Drop for CircuitPlayground {
+    // Leaves the board quiet and dark so nothing stays on after the program
+    // exits. A board that is already gone is left alone, and errors can't be
+    // returned from here, so they are ignored; call `stop_all` first to see
+    // them.
+    fn drop(&mut self) {
+        if !self.is_connected() {
+            return;
+        }
+        let _ = self.stop_all();
+    }
+}
+
+impl fmt::Debug for CircuitPlayground {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CircuitPlayground")
+            .field("port", &self.port_name())
+            .field("firmware_name", &self.board.firmware_name)
+            .field("firmware_version", &self.board.firmware_version)
+            .field("implementation_version", &self.version)
+            .field("reply_timeout", &self.reply_timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for CircuitPlayground {
+    // A one-line summary such as "Circuit Playground on /dev/ttyACM0
+    // (firmware 2.5, implementation 1.0.0)". Versions that aren't known, such
+    // as an implementation version that hasn't been queried, are left out.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Circuit Playground")?;
+        if let Some(port) = self.port_name() {
+            write!(f, " on {}", port)?;
+        }
+
+        let mut versions = vec![];
+        if !self.board.firmware_version.is_empty() {
+            versions.push(format!("firmware {}", self.board.firmware_version));
+        }
+        if let Some(version) = self.version {
+            versions.push(format!("implementation {}", version));
+        }
+        if !versions.is_empty() {
+            write!(f, " ({})", versions.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns a board talking to a mock, along with the mock to check what
+    // was written to it.
+    fn mock_board() -> (CircuitPlayground, MockTransport) {
+        let mock = MockTransport::new();
+        (CircuitPlayground::with_transport(mock.clone()), mock)
+    }
+
+    // Returns the sub-command of every Circuit Playground command in
+    // `written`, in order.
+    fn sub_commands(written: &[u8]) -> Vec<u8> {
+        written.windows(3)
+            .filter(|window| window[0] == START_SYSEX && window[1] == CP_COMMAND)
+            .map(|window| window[2])
+            .collect()
+    }
+
+    // Encodes the data of a CP_CAP_REPLY.
+    fn cap_data(pin: u8, value: i32) -> Vec<u8> {
+        let mut data = vec![pin];
+        data.extend_from_slice(&protocol::encode_7bit_u32(value as u32));
+        data
+    }
+
+    // Returns the frequency of every CP_TONE command in `written`, in order.
+    fn tone_frequencies(written: &[u8]) -> Vec<u16> {
+        written.windows(5)
+            .filter(|window| window[..3] == [START_SYSEX, CP_COMMAND, CP_TONE])
+            .map(|window| window[3] as u16 | (window[4] as u16) << 7)
+            .collect()
+    }
+
+    // Encodes the data of a CP_ACCEL_READ_REPLY.
+    fn accel_data(x: f32, y: f32, z: f32) -> Vec<u8> {
+        [x, y, z].iter().flat_map(|&value| protocol::encode_7bit_float(value).to_vec()).collect()
+    }
+
+    #[test]
+    fn step_toward_takes_the_short_way_round() {
+        let cases = [(0, 0, 0), (0, 3, 1), (0, 7, 9), (9, 1, 0), (2, 7, 3), (5, 4, 4)];
+
+        for &(from, to, expected) in cases.iter() {
+            assert_eq!(step_toward(from, to), expected, "from {} to {}", from, to);
+        }
+    }
+
+    #[test]
+    fn step_toward_wraps_indexes_past_the_ring() {
+        assert_eq!(step_toward(255, 0), 6);
+        assert_eq!(step_toward(0, 255), 1);
+        assert_eq!(step_toward(12, 12), 2);
+    }
+
+    #[test]
+    fn queries_drain_readings_left_by_a_stream() {
+        let (mut cp, mock) = mock_board();
+        cp.set_reply_timeout(Duration::from_millis(20));
+
+        cp.start_accel_stream().unwrap().stop().unwrap();
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(1.0, 2.0, 3.0));
+        assert!(matches!(cp.read_accelerometer(), Err(CpError::Timeout)));
+
+        // Only the first query after the stream drains.
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(1.0, 2.0, 3.0));
+        assert_eq!(cp.read_accelerometer().unwrap(), (1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn button_callbacks_only_fire_on_changes() {
+        let (mut cp, mock) = mock_board();
+        cp.set_reply_timeout(Duration::from_millis(5));
+
+        let presses = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = presses.clone();
+        cp.on_button_a(move |pressed| recorded.lock().unwrap().push(pressed)).unwrap();
+
+        // Button A is pin 4, the fifth bit of port 0's report.
+        for &value in [0x00, 0x10, 0x10, 0x00].iter() {
+            mock.queue_reply(&[DIGITAL_MESSAGE, value, 0x00]);
+            cp.read_and_dispatch().unwrap();
+        }
+        assert_eq!(*presses.lock().unwrap(), vec![true, false]);
+    }
+
+    #[test]
+    fn first_button_report_is_not_a_press() {
+        let (mut cp, mock) = mock_board();
+        cp.set_reply_timeout(Duration::from_millis(5));
+
+        let presses = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = presses.clone();
+        cp.on_button_a(move |pressed| recorded.lock().unwrap().push(pressed)).unwrap();
+
+        mock.queue_reply(&[DIGITAL_MESSAGE, 0x10, 0x00]);
+        cp.read_and_dispatch().unwrap();
+        assert!(presses.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn is_shake_needs_several_hard_samples() {
+        let rest = (0.0, 0.0, GRAVITY);
+        let jolt = (2.0 * GRAVITY, 0.0, 0.0);
+
+        assert!(!is_shake(&[rest; 20], SHAKE_GENTLE_G, SHAKE_MIN_SAMPLES));
+        assert!(!is_shake(&[rest, jolt, rest, jolt, rest], SHAKE_GENTLE_G, SHAKE_MIN_SAMPLES));
+        assert!(is_shake(&[rest, jolt, rest, jolt, jolt, rest], SHAKE_GENTLE_G, SHAKE_MIN_SAMPLES));
+        assert!(!is_shake(&[jolt; 5], SHAKE_HARD_G, SHAKE_MIN_SAMPLES));
+        assert!(!is_shake(&[], SHAKE_GENTLE_G, SHAKE_MIN_SAMPLES));
+    }
+
+    #[test]
+    fn therm_value_to_celsius_is_nan_at_the_ends_of_the_range() {
+        assert!(therm_value_to_celsius(0).is_nan());
+        assert!(therm_value_to_celsius(1023).is_nan());
+        assert!(therm_value_to_celsius(u16::MAX).is_nan());
+        assert!(!therm_value_to_celsius(1).is_nan());
+        assert!(!therm_value_to_celsius(1022).is_nan());
+    }
+
+    #[test]
+    fn converts_temperatures_between_units() {
+        let temperature = Temperature::from_celsius(25.0);
+
+        assert_eq!(temperature.celsius(), 25.0);
+        assert!((temperature.fahrenheit() - 77.0).abs() < 1e-9);
+        assert!((temperature.kelvin() - 298.15).abs() < 1e-9);
+        assert!((Temperature::from_celsius(-40.0).fahrenheit() + 40.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn set_pixel_sends_one_pixel_set_without_showing() {
+        let (mut cp, mock) = mock_board();
+
+        cp.set_pixel(0, (255, 0, 0)).unwrap();
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_PIXEL_SET, 0, 127, 64, 0, 0, END_SYSEX]);
+    }
+
+    #[test]
+    fn set_pixel_rejects_pixels_past_the_ring() {
+        let (mut cp, mock) = mock_board();
+
+        assert!(matches!(cp.set_pixel(10, (255, 0, 0)), Err(CpError::InvalidPixel(10))));
+        assert!(mock.take_written().is_empty());
+    }
+
+    #[test]
+    fn show_pixels_sends_the_show_command() {
+        let (mut cp, mock) = mock_board();
+
+        cp.show_pixels().unwrap();
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_PIXEL_SHOW, END_SYSEX]);
+    }
+
+    #[test]
+    fn clear_pixels_sends_the_clear_command() {
+        let (mut cp, mock) = mock_board();
+
+        cp.set_pixel(3, Color::BLUE).unwrap();
+        mock.take_written();
+
+        cp.clear_pixels().unwrap();
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_PIXEL_CLEAR, END_SYSEX]);
+        assert_eq!(cp.pixels[3], Color::BLACK);
+    }
+
+    #[test]
+    fn set_brightness_accepts_0_to_100() {
+        let (mut cp, mock) = mock_board();
+
+        for &level in [0, 100].iter() {
+            cp.set_brightness(Brightness::new(level).unwrap()).unwrap();
+            assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_PIXEL_BRIGHTNESS, level, END_SYSEX]);
+        }
+        assert!(matches!(Brightness::new(101), Err(CpError::InvalidBrightness(101))));
+    }
+
+    #[test]
+    fn fill_sets_every_pixel_and_shows_once() {
+        let (mut cp, mock) = mock_board();
+
+        cp.fill(Color::GREEN).unwrap();
+
+        let mut expected = vec![CP_PIXEL_SET; 10];
+        expected.push(CP_PIXEL_SHOW);
+        assert_eq!(sub_commands(&mock.take_written()), expected);
+        assert_eq!(cp.pixels, vec![Color::GREEN; 10]);
+    }
+
+    #[test]
+    fn set_pixels_sets_each_pixel_in_order() {
+        let (mut cp, mock) = mock_board();
+        let colors: Vec<Color> = (0..10).map(|i| Color::new(i * 2, 0, 0)).collect();
+
+        cp.set_pixels(&colors).unwrap();
+
+        let written = mock.take_written();
+        for (index, color) in colors.iter().enumerate() {
+            let data = color.to_firmata_bytes();
+            let frame = [START_SYSEX, CP_COMMAND, CP_PIXEL_SET, index as u8, data[0], data[1], data[2], data[3], END_SYSEX];
+            assert_eq!(&written[index * 9..index * 9 + 9], &frame);
+        }
+        assert_eq!(&written[90..], &[START_SYSEX, CP_COMMAND, CP_PIXEL_SHOW, END_SYSEX]);
+    }
+
+    #[test]
+    fn set_pixels_needs_one_color_per_pixel() {
+        let (mut cp, mock) = mock_board();
+
+        let result = cp.set_pixels(&[Color::RED; 9]);
+        assert!(matches!(result, Err(CpError::InvalidPixelCount { expected: 10, found: 9 })));
+        assert!(mock.take_written().is_empty());
+    }
+
+    #[test]
+    fn play_tone_splits_frequency_and_duration_into_7_bits() {
+        let (mut cp, mock) = mock_board();
+
+        // 440 is 3 * 128 + 56 and 500 is 3 * 128 + 116.
+        cp.play_tone(440, 500).unwrap();
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_TONE, 56, 3, 116, 3, END_SYSEX]);
+    }
+
+    #[test]
+    fn play_tone_rejects_values_past_14_bits() {
+        let (mut cp, mock) = mock_board();
+
+        assert!(matches!(cp.play_tone(0x4000, 100), Err(CpError::InvalidFrequency(0x4000))));
+        assert!(matches!(cp.play_tone(440, 0x4000), Err(CpError::InvalidDuration(0x4000))));
+        assert!(mock.take_written().is_empty());
+    }
+
+    #[test]
+    fn stop_tone_sends_no_tone() {
+        let (mut cp, mock) = mock_board();
+
+        cp.stop_tone().unwrap();
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_NO_TONE, END_SYSEX]);
+    }
+
+    #[test]
+    fn play_note_plays_the_note_or_stops_for_a_rest() {
+        let (mut cp, mock) = mock_board();
+
+        cp.play_note(Note::A4, 500).unwrap();
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_TONE, 56, 3, 116, 3, END_SYSEX]);
+
+        cp.play_note(Note::Rest, 500).unwrap();
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_NO_TONE, END_SYSEX]);
+    }
+
+    #[test]
+    fn play_melody_plays_notes_in_order() {
+        let (mut cp, mock) = mock_board();
+
+        cp.play_melody(&[(Note::C4, 1), (Note::Rest, 1), (Note::G4, 2)]).unwrap();
+
+        // C4 is 262 hz, 2 * 128 + 6, and G4 is 392 hz, 3 * 128 + 8.
+        assert_eq!(mock.take_written(), vec![
+            START_SYSEX, CP_COMMAND, CP_TONE, 6, 2, 1, 0, END_SYSEX,
+            START_SYSEX, CP_COMMAND, CP_NO_TONE, END_SYSEX,
+            START_SYSEX, CP_COMMAND, CP_TONE, 8, 3, 2, 0, END_SYSEX,
+        ]);
+    }
+
+    #[test]
+    fn read_accelerometer_decodes_the_reply() {
+        let (mut cp, mock) = mock_board();
+
+        // A tap reply arriving first is skipped.
+        mock.queue_cp_reply(CP_ACCEL_TAP_REPLY, &[0x10, 0]);
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(0.0, -0.5, 9.8));
+        assert_eq!(cp.read_accelerometer().unwrap(), (0.0, -0.5, 9.8));
+
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &[0; 8]);
+        assert!(matches!(cp.read_accelerometer(), Err(CpError::MalformedReply(CP_ACCEL_READ_REPLY))));
+    }
+
+    #[test]
+    fn read_accelerometer_times_out_without_a_reply() {
+        let (mut cp, _mock) = mock_board();
+        cp.set_reply_timeout(Duration::from_millis(10));
+
+        assert!(matches!(cp.read_accelerometer(), Err(CpError::Timeout)));
+    }
+
+    #[test]
+    fn accel_ranges_map_to_their_bytes() {
+        let (mut cp, mock) = mock_board();
+        let cases = [(AccelRange::G2, 0), (AccelRange::G4, 1), (AccelRange::G8, 2), (AccelRange::G16, 3)];
+
+        for &(range, byte) in cases.iter() {
+            assert_eq!(range.to_byte(), byte);
+
+            cp.set_accel_range(range).unwrap();
+            assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_ACCEL_RANGE, byte, END_SYSEX]);
+        }
+    }
+
+    #[test]
+    fn read_tap_decodes_the_click_register() {
+        let (mut cp, mock) = mock_board();
+
+        mock.queue_cp_reply(CP_ACCEL_TAP_REPLY, &[0x20, 0]);
+        assert_eq!(cp.read_tap().unwrap(), TapState { single: false, double: true });
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_ACCEL_TAP, END_SYSEX]);
+
+        mock.queue_cp_reply(CP_ACCEL_TAP_REPLY, &[0x10, 0]);
+        assert_eq!(cp.read_tap().unwrap(), TapState { single: true, double: false });
+    }
+
+    #[test]
+    fn set_tap_config_splits_both_values() {
+        let (mut cp, mock) = mock_board();
+
+        cp.set_tap_config(ClickType::SingleDouble, 80).unwrap();
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_ACCEL_TAP_CONFIG, 2, 0, 80, 0, END_SYSEX]);
+
+        // 200 is 128 + 72.
+        cp.set_tap_config(ClickType::Single, 200).unwrap();
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_ACCEL_TAP_CONFIG, 1, 0, 72, 1, END_SYSEX]);
+    }
+
+    #[test]
+    fn accel_stream_calls_back_for_every_reading() {
+        let (mut cp, mock) = mock_board();
+        let readings = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = readings.clone();
+
+        cp.on_accel(move |x, y, z| recorded.lock().unwrap().push((x, y, z)));
+        {
+            let mut stream = cp.start_accel_stream().unwrap();
+            assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_ACCEL_STREAM_ON, END_SYSEX]);
+
+            mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(1.0, 0.0, 9.5));
+            mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(2.0, 0.0, 9.0));
+            stream.read_and_dispatch().unwrap();
+            stream.read_and_dispatch().unwrap();
+        }
+
+        assert_eq!(*readings.lock().unwrap(), vec![(1.0, 0.0, 9.5), (2.0, 0.0, 9.0)]);
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_ACCEL_STREAM_OFF, END_SYSEX]);
+    }
+
+    #[test]
+    fn tap_stream_calls_back_for_every_reply() {
+        let (mut cp, mock) = mock_board();
+        let taps = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = taps.clone();
+
+        cp.on_tap(move |state| recorded.lock().unwrap().push(state));
+        let mut stream = cp.start_tap_stream().unwrap();
+
+        mock.queue_cp_reply(CP_ACCEL_TAP_REPLY, &[0x10, 0]);
+        mock.queue_cp_reply(CP_ACCEL_TAP_REPLY, &[0x30, 0]);
+        stream.read_and_dispatch().unwrap();
+        stream.read_and_dispatch().unwrap();
+        stream.stop().unwrap();
+
+        assert_eq!(*taps.lock().unwrap(), vec![
+            TapState { single: true, double: false },
+            TapState { single: true, double: true },
+        ]);
+    }
+
+    #[test]
+    fn classifies_orientation_by_the_dominant_axis() {
+        let cases = [
+            ((0.0, 0.0, 9.8), Some(Orientation::FaceUp)),
+            ((0.0, 0.0, -9.8), Some(Orientation::FaceDown)),
+            ((9.8, 0.0, 1.0), Some(Orientation::TiltLeft)),
+            ((-9.8, 1.0, 0.0), Some(Orientation::TiltRight)),
+            ((0.5, 9.8, 0.0), Some(Orientation::TiltForward)),
+            ((0.0, -9.8, 0.5), Some(Orientation::TiltBack)),
+            ((6.0, 0.0, 6.0), None),
+        ];
+
+        for &((x, y, z), expected) in cases.iter() {
+            assert_eq!(Orientation::from_acceleration(x, y, z, ORIENTATION_THRESHOLD), expected, "{:?}", (x, y, z));
+        }
+    }
+
+    #[test]
+    fn read_orientation_keeps_the_last_one_between_orientations() {
+        let (mut cp, mock) = mock_board();
+
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(0.0, -9.8, 0.0));
+        assert_eq!(cp.read_orientation().unwrap(), Orientation::TiltBack);
+
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(0.0, -6.0, 6.0));
+        assert_eq!(cp.read_orientation().unwrap(), Orientation::TiltBack);
+    }
+
+    #[test]
+    fn pitch_and_roll_from_gravity() {
+        let close = |actual: f32, expected: f32| (actual - expected).abs() < 0.01;
+
+        let (pitch, roll) = pitch_roll(0.0, 0.0, 9.8);
+        assert!(close(pitch, 0.0) && close(roll, 0.0), "{} {}", pitch, roll);
+
+        // 30 degrees of tilt puts half of gravity along the tilted axis.
+        let (pitch, roll) = pitch_roll(-4.9, 0.0, 8.487);
+        assert!(close(pitch, 30.0) && close(roll, 0.0), "{} {}", pitch, roll);
+
+        let (pitch, roll) = pitch_roll(0.0, 4.9, 8.487);
+        assert!(close(pitch, 0.0) && close(roll, 30.0), "{} {}", pitch, roll);
+    }
+
+    #[test]
+    fn cap_read_returns_the_reading_for_its_pin() {
+        let (mut cp, mock) = mock_board();
+
+        // A streamed reading of another pin is skipped.
+        mock.queue_cp_reply(CP_CAP_REPLY, &cap_data(1, 50));
+        mock.queue_cp_reply(CP_CAP_REPLY, &cap_data(3, 1234));
+        assert_eq!(cp.cap_read(3).unwrap(), 1234);
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_CAP_READ, 3, END_SYSEX]);
+    }
+
+    #[test]
+    fn cap_read_rejects_pins_without_cap_touch() {
+        let (mut cp, mock) = mock_board();
+
+        assert!(matches!(cp.cap_read(4), Err(CpError::InvalidCapPin(4))));
+        assert!(mock.take_written().is_empty());
+    }
+
+    #[test]
+    fn is_touched_needs_a_reading_above_the_threshold() {
+        let (mut cp, mock) = mock_board();
+
+        for &(value, touched) in [(0, false), (300, false), (301, true), (-5, false)].iter() {
+            mock.queue_cp_reply(CP_CAP_REPLY, &cap_data(0, value));
+            assert_eq!(cp.is_touched(0).unwrap(), touched, "reading {}", value);
+        }
+
+        cp.set_cap_threshold(500);
+        mock.queue_cp_reply(CP_CAP_REPLY, &cap_data(0, 400));
+        assert!(!cp.is_touched(0).unwrap());
+    }
+
+    #[test]
+    fn cap_streams_call_back_with_only_their_own_pin() {
+        let (mut cp, mock) = mock_board();
+        let pin_1 = Arc::new(std::sync::Mutex::new(vec![]));
+        let pin_3 = Arc::new(std::sync::Mutex::new(vec![]));
+
+        let recorded = pin_1.clone();
+        cp.on_cap(1, move |value| recorded.lock().unwrap().push(value));
+        let recorded = pin_3.clone();
+        cp.on_cap(3, move |value| recorded.lock().unwrap().push(value));
+
+        {
+            let mut first = cp.start_cap_stream(1).unwrap();
+            let mut both = first.start_cap_stream(3).unwrap();
+
+            for &(pin, value) in [(1, 10), (3, 30), (3, 31), (1, 11)].iter() {
+                mock.queue_cp_reply(CP_CAP_REPLY, &cap_data(pin, value));
+                both.read_and_dispatch().unwrap();
+            }
+        }
+
+        assert_eq!(*pin_1.lock().unwrap(), vec![10, 11]);
+        assert_eq!(*pin_3.lock().unwrap(), vec![30, 31]);
+        assert_eq!(sub_commands(&mock.take_written()), vec![CP_CAP_ON, CP_CAP_ON, CP_CAP_OFF, CP_CAP_OFF]);
+    }
+
+    #[test]
+    fn sense_color_decodes_the_reply() {
+        let (mut cp, mock) = mock_board();
+
+        // 200 is split into 72 and 1.
+        mock.queue_cp_reply(CP_SENSECOLOR_REPLY, &[72, 1, 100, 0, 50, 0]);
+        assert_eq!(cp.sense_color().unwrap(), Color::new(200, 100, 50));
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_SENSECOLOR, END_SYSEX]);
+    }
+
+    #[test]
+    fn read_temperature_c_converts_the_thermistor_reading() {
+        let (mut cp, mock) = mock_board();
+
+        // Half scale, 512, is 4 * 128. The thermistor then matches the
+        // series resistor, so it's at its nominal 25C.
+        mock.queue_reply(&[ANALOG_MESSAGE | THERM_PIN, 0, 4]);
+        let celsius = cp.read_temperature_c().unwrap();
+        assert!((celsius - 24.956).abs() < 0.001, "{}", celsius);
+    }
+
+    #[test]
+    fn read_light_level_returns_the_reported_value() {
+        let (mut cp, mock) = mock_board();
+
+        // A reading of another channel is skipped. 700 is 5 * 128 + 60.
+        mock.queue_reply(&[ANALOG_MESSAGE | SOUND_PIN, 1, 0, ANALOG_MESSAGE | LIGHT_PIN, 60, 5]);
+        assert_eq!(cp.read_light_level().unwrap(), 700);
+
+        // Reporting is turned on for the channel and back off again.
+        assert_eq!(mock.take_written(), vec![REPORT_ANALOG | LIGHT_PIN, 1, REPORT_ANALOG | LIGHT_PIN, 0]);
+    }
+
+    #[test]
+    fn peak_to_peak_is_the_spread_of_the_samples() {
+        assert_eq!(peak_to_peak(&[512, 530, 490, 601, 505]), 111);
+        assert_eq!(peak_to_peak(&[512]), 0);
+        assert_eq!(peak_to_peak(&[]), 0);
+    }
+
+    #[test]
+    fn read_sound_peak_collects_the_samples() {
+        let (mut cp, mock) = mock_board();
+
+        mock.queue_reply(&[ANALOG_MESSAGE | SOUND_PIN, 0, 4, ANALOG_MESSAGE | SOUND_PIN, 100, 4, ANALOG_MESSAGE | SOUND_PIN, 20, 3]);
+        assert_eq!(cp.read_sound_peak(3).unwrap(), 612 - 404);
+    }
+
+    #[test]
+    fn buttons_read_their_bit_of_the_port_report() {
+        let (mut cp, mock) = mock_board();
+
+        // Button A is pin 4 on port 0.
+        mock.queue_reply(&[DIGITAL_MESSAGE, 0x10, 0]);
+        assert!(cp.button_a().unwrap());
+        assert_eq!(mock.take_written(), vec![PIN_MODE, BUTTON_A_PIN, INPUT, REPORT_DIGITAL, 1, REPORT_DIGITAL, 0]);
+
+        mock.queue_reply(&[DIGITAL_MESSAGE, 0x0F, 0]);
+        assert!(!cp.button_a().unwrap());
+
+        // Button B is pin 19, the fourth bit of port 2. A report for
+        // another port is skipped.
+        mock.queue_reply(&[DIGITAL_MESSAGE, 0x7F, 1, DIGITAL_MESSAGE | 2, 0x08, 0]);
+        assert!(cp.button_b().unwrap());
+
+        mock.queue_reply(&[DIGITAL_MESSAGE | 2, 0x77, 1]);
+        assert!(!cp.button_b().unwrap());
+    }
+
+    #[test]
+    fn slide_switch_reads_high_on_the_left() {
+        let (mut cp, mock) = mock_board();
+
+        // The switch is pin 21, the sixth bit of port 2.
+        mock.queue_reply(&[DIGITAL_MESSAGE | 2, 0x20, 0]);
+        assert_eq!(cp.slide_switch().unwrap(), SwitchPosition::Left);
+        assert_eq!(mock.take_written(), vec![PIN_MODE, SLIDE_SWITCH_PIN, INPUT_PULLUP, REPORT_DIGITAL | 2, 1, REPORT_DIGITAL | 2, 0]);
+
+        mock.queue_reply(&[DIGITAL_MESSAGE | 2, 0x5F, 1]);
+        assert_eq!(cp.slide_switch().unwrap(), SwitchPosition::Right);
+    }
+
+    #[test]
+    fn with_transport_builds_a_board_with_the_defaults() {
+        let (cp, mock) = mock_board();
+
+        assert_eq!(cp.board.pins.len(), 32);
+        assert_eq!(cp.pixel_count(), DEFAULT_PIXEL_COUNT);
+        assert_eq!(cp.reply_timeout(), DEFAULT_REPLY_TIMEOUT);
+        assert_eq!(cp.port_name(), None);
+        assert!(cp.is_connected());
+        assert!(mock.written().is_empty());
+    }
+
+    #[test]
+    fn validation_paths_give_their_own_errors() {
+        let (mut cp, mock) = mock_board();
+
+        assert!(matches!(cp.set_pixel(10, Color::RED), Err(CpError::InvalidPixel(10))));
+        assert!(matches!(cp.set_pixels(&[]), Err(CpError::InvalidPixelCount { expected: 10, found: 0 })));
+        assert!(matches!(cp.start_cap_stream(5).map(|_| ()), Err(CpError::InvalidCapPin(5))));
+        assert!(matches!(cp.stop_cap_stream(7), Err(CpError::InvalidCapPin(7))));
+        assert!(matches!(cp.set_pin_mode(32, OUTPUT), Err(CpError::InvalidPin(32))));
+        assert!(matches!(cp.play_tone(20000, 10), Err(CpError::InvalidFrequency(20000))));
+        assert!(matches!(cp.play_tone(440, 20000), Err(CpError::InvalidDuration(20000))));
+        assert!(matches!(Brightness::new(255), Err(CpError::InvalidBrightness(255))));
+        assert!(mock.take_written().is_empty());
+    }
+
+    #[test]
+    fn sub_commands_fit_in_a_sysex_data_byte() {
+        let commands = [
+            CP_COMMAND, CP_PIXEL_SET, CP_PIXEL_SHOW, CP_PIXEL_CLEAR, CP_PIXEL_BRIGHTNESS, CP_TONE, CP_NO_TONE,
+            CP_ACCEL_READ, CP_ACCEL_TAP, CP_ACCEL_READ_REPLY, CP_ACCEL_TAP_REPLY,
+            CP_ACCEL_TAP_STREAM_ON, CP_ACCEL_TAP_STREAM_OFF, CP_ACCEL_STREAM_ON, CP_ACCEL_STREAM_OFF,
+            CP_ACCEL_RANGE, CP_ACCEL_TAP_CONFIG, CP_CAP_READ, CP_CAP_ON, CP_CAP_OFF, CP_CAP_REPLY,
+            CP_SENSECOLOR, CP_SENSECOLOR_REPLY, CP_IMPL_VERS, CP_IMPL_VERS_REPLY,
+        ];
+
+        assert!(commands.iter().all(|command| command & 0x80 == 0));
+    }
+
+    #[test]
+    fn frames_commands_in_sysex() {
+        let mut buf = [0; 16];
+        let len = write_cp_command(&mut buf, CP_PIXEL_BRIGHTNESS, &[50]);
+        assert_eq!(&buf[..len], &[START_SYSEX, CP_COMMAND, CP_PIXEL_BRIGHTNESS, 50, END_SYSEX]);
+
+        // Data bytes lose their top bit so they can't end the message.
+        let mut framed = vec![0xAA];
+        frame_cp_command(&mut framed, CP_CAP_ON, &[0xFF, 0x03]);
+        assert_eq!(framed, vec![0xAA, START_SYSEX, CP_COMMAND, CP_CAP_ON, 0x7F, 0x03, END_SYSEX]);
+    }
+
+    #[test]
+    fn reassembles_a_reply_split_across_reads() {
+        let (mut cp, mock) = mock_board();
+        let mut frame = vec![START_SYSEX, CP_COMMAND, CP_CAP_REPLY];
+        frame.extend(cap_data(2, 450));
+        frame.push(END_SYSEX);
+
+        mock.queue_reply(&frame[..5]);
+        assert_eq!(cp.poll().unwrap(), None);
+
+        mock.queue_reply(&frame[5..]);
+        assert_eq!(cp.poll().unwrap(), Some(CpEvent::Cap(2, 450)));
+        assert_eq!(cp.poll().unwrap(), None);
+    }
+
+    #[test]
+    fn drops_a_reply_cut_short_by_another_message() {
+        let (mut cp, mock) = mock_board();
+
+        mock.queue_reply(&[START_SYSEX, CP_COMMAND, CP_CAP_REPLY, 2, 0x10]);
+        mock.queue_cp_reply(CP_ACCEL_TAP_REPLY, &[0x10, 0]);
+        assert_eq!(cp.poll().unwrap(), Some(CpEvent::Tap(TapState { single: true, double: false })));
+        assert_eq!(cp.poll().unwrap(), None);
+    }
+
+    #[test]
+    fn implementation_version_is_queried_once() {
+        let (mut cp, mock) = mock_board();
+
+        mock.queue_cp_reply(CP_IMPL_VERS_REPLY, &[1, 2, 3]);
+        let version = cp.implementation_version().unwrap();
+        assert_eq!(version, Version { major: 1, minor: 2, bugfix: 3 });
+        assert_eq!(version.to_string(), "1.2.3");
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_IMPL_VERS, END_SYSEX]);
+
+        // The cached version is returned without asking again.
+        assert_eq!(cp.implementation_version().unwrap(), version);
+        assert!(mock.take_written().is_empty());
+    }
+
+    #[test]
+    fn require_version_compares_major_then_minor() {
+        let cases = [((1, 2), true), ((1, 1), true), ((0, 9), true), ((1, 3), false), ((2, 0), false)];
+
+        for &((major, minor), supported) in cases.iter() {
+            let (mut cp, mock) = mock_board();
+            mock.queue_cp_reply(CP_IMPL_VERS_REPLY, &[1, 2, 0]);
+
+            match cp.require_version(major, minor) {
+                Ok(()) => assert!(supported, "{}.{}", major, minor),
+                Err(CpError::VersionTooOld { required, found }) => {
+                    assert!(!supported, "{}.{}", major, minor);
+                    assert_eq!(required, (major, minor));
+                    assert_eq!(found, Version { major: 1, minor: 2, bugfix: 0 });
+                }
+                Err(err) => panic!("unexpected error {}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn queries_give_up_after_the_reply_timeout() {
+        let (mut cp, _mock) = mock_board();
+        let timeout = Duration::from_millis(30);
+        cp.set_reply_timeout(timeout);
+
+        type Query = fn(&mut CircuitPlayground) -> Result<(), CpError>;
+        let queries: [Query; 5] = [
+            |cp| cp.read_accelerometer().map(|_| ()),
+            |cp| cp.read_tap().map(|_| ()),
+            |cp| cp.sense_color().map(|_| ()),
+            |cp| cp.read_light_level().map(|_| ()),
+            |cp| cp.button_a().map(|_| ()),
+        ];
+
+        for query in queries.iter() {
+            let start = Instant::now();
+            assert!(matches!(query(&mut cp), Err(CpError::Timeout)));
+
+            let elapsed = start.elapsed();
+            assert!(elapsed >= timeout && elapsed < timeout * 5, "{:?}", elapsed);
+        }
+    }
+
+    #[test]
+    fn dropping_the_board_turns_everything_off() {
+        let (cp, mock) = mock_board();
+        drop(cp);
+
+        let mut expected = vec![CP_ACCEL_STREAM_OFF, CP_ACCEL_TAP_STREAM_OFF];
+        expected.extend(vec![CP_CAP_OFF; CAP_PINS.len()]);
+        expected.extend(&[CP_NO_TONE, CP_PIXEL_CLEAR, CP_PIXEL_SHOW]);
+
+        let written = mock.take_written();
+        assert_eq!(sub_commands(&written), expected);
+        for &pin in CAP_PINS.iter() {
+            assert!(written.windows(5).any(|window| window == [START_SYSEX, CP_COMMAND, CP_CAP_OFF, pin, END_SYSEX]));
+        }
+    }
+
+    #[test]
+    fn blink_fills_and_clears_once_per_blink() {
+        let (mut cp, mock) = mock_board();
+
+        cp.blink(Color::RED, 3, 0, 0).unwrap();
+
+        let mut cycle = vec![CP_PIXEL_SET; 10];
+        cycle.extend(&[CP_PIXEL_SHOW, CP_PIXEL_CLEAR, CP_PIXEL_SHOW]);
+        assert_eq!(sub_commands(&mock.take_written()), cycle.repeat(3));
+    }
+
+    #[test]
+    fn rainbow_cycle_shows_one_frame_per_iteration() {
+        let (mut cp, mock) = mock_board();
+
+        cp.rainbow_cycle(4, 0).unwrap();
+
+        let subs = sub_commands(&mock.take_written());
+        assert_eq!(subs.iter().filter(|&&sub| sub == CP_PIXEL_SHOW).count(), 4);
+        assert_eq!(subs.iter().filter(|&&sub| sub == CP_PIXEL_SET).count(), 40);
+
+        // The last frame has turned three steps from the first.
+        assert_eq!(cp.pixels[0], color_wheel(3));
+        assert_eq!(cp.pixels[5], color_wheel(128 + 3));
+    }
+
+    #[test]
+    fn pulse_brightness_rises_then_falls() {
+        let (mut cp, mock) = mock_board();
+
+        cp.pulse(Color::BLUE, 10 * PULSE_FRAME_MS, 1, Easing::Linear).unwrap();
+
+        let levels: Vec<u8> = mock.take_written().windows(4)
+            .filter(|window| window[..3] == [START_SYSEX, CP_COMMAND, CP_PIXEL_BRIGHTNESS])
+            .map(|window| window[3])
+            .collect();
+        assert_eq!(levels, vec![0, 20, 40, 60, 80, 100, 80, 60, 40, 20, 100]);
+        assert_eq!(cp.brightness, Some(Brightness::MAX));
+    }
+
+    #[test]
+    fn sine_pulse_breathes_along_a_cosine() {
+        let (mut cp, mock) = mock_board();
+
+        cp.pulse(Color::BLUE, 10 * PULSE_FRAME_MS, 1, Easing::Sine).unwrap();
+
+        // (1 - cos(2 pi step / 10)) / 2, as a brightness level.
+        let levels: Vec<u8> = mock.take_written().windows(4)
+            .filter(|window| window[..3] == [START_SYSEX, CP_COMMAND, CP_PIXEL_BRIGHTNESS])
+            .map(|window| window[3])
+            .collect();
+        assert_eq!(levels, vec![0, 10, 35, 65, 90, 100, 90, 65, 35, 10, 100]);
+    }
+
+    #[test]
+    fn show_progress_lights_a_share_of_the_ring() {
+        let (mut cp, _mock) = mock_board();
+        let lit = |cp: &CircuitPlayground| cp.pixels.iter().filter(|&&color| color != Color::BLACK).count();
+
+        cp.show_progress(0.0, Color::GREEN).unwrap();
+        assert_eq!(lit(&cp), 0);
+
+        cp.show_progress(0.5, Color::GREEN).unwrap();
+        assert_eq!(lit(&cp), 5);
+        assert_eq!(cp.pixels[4], Color::GREEN);
+
+        cp.show_progress(1.0, Color::GREEN).unwrap();
+        assert_eq!(cp.pixels, vec![Color::GREEN; 10]);
+
+        // A quarter of the third pixel is covered.
+        cp.show_progress(0.225, Color::WHITE).unwrap();
+        assert_eq!(&cp.pixels[..3], &[Color::WHITE, Color::WHITE, Color::new(64, 64, 64)]);
+    }
+
+    #[test]
+    fn analog_passthrough_sends_firmata_messages() {
+        let (mut cp, mock) = mock_board();
+
+        // 200 is 128 + 72.
+        cp.analog_write(10, 200).unwrap();
+        assert_eq!(mock.take_written(), vec![ANALOG_MESSAGE | 10, 72, 1]);
+
+        mock.queue_reply(&[ANALOG_MESSAGE | 7, 0x7F, 7]);
+        assert_eq!(cp.analog_read(7).unwrap(), 1023);
+        assert_eq!(mock.take_written(), vec![REPORT_ANALOG | 7, 1, REPORT_ANALOG | 7, 0]);
+
+        assert!(matches!(cp.analog_write(16, 1), Err(CpError::InvalidPin(16))));
+        assert!(matches!(cp.analog_read(16), Err(CpError::InvalidPin(16))));
+        assert!(mock.take_written().is_empty());
+    }
+
+    #[test]
+    fn one_shot_reads_leave_reporting_on_for_callbacks() {
+        let (mut cp, mock) = mock_board();
+        cp.on_button_a(|_| {}).unwrap();
+        mock.take_written();
+
+        mock.queue_reply(&[DIGITAL_MESSAGE, 0x10, 0]);
+        assert!(cp.button_a().unwrap());
+        assert_eq!(mock.take_written(), vec![PIN_MODE, BUTTON_A_PIN, INPUT, REPORT_DIGITAL, 1]);
+
+        // Button B is on another port, which nothing else is listening to.
+        mock.queue_reply(&[DIGITAL_MESSAGE | 2, 0x08, 0]);
+        assert!(cp.button_b().unwrap());
+        assert_eq!(mock.take_written(), vec![PIN_MODE, BUTTON_B_PIN, INPUT, REPORT_DIGITAL | 2, 1, REPORT_DIGITAL | 2, 0]);
+    }
+
+    #[test]
+    fn reset_is_retried_after_transient_errors() {
+        let (mut cp, mock) = flaky_board(&[io::ErrorKind::WouldBlock, io::ErrorKind::Interrupted]);
+
+        mock.queue_reply(&[START_SYSEX, REPORT_FIRMWARE, 2, 5, END_SYSEX]);
+        cp.reset().unwrap();
+        assert_eq!(mock.take_written(), vec![SYSTEM_RESET, START_SYSEX, REPORT_FIRMWARE, END_SYSEX]);
+    }
+
+    #[test]
+    fn downhill_pixel_points_away_from_the_raised_side() {
+        assert_eq!(downhill_pixel(-5.0, 0.0), Some(7));
+        assert_eq!(downhill_pixel(5.0, 0.0), Some(2));
+        assert_eq!(downhill_pixel(-3.0, -5.2), Some(5));
+        assert_eq!(downhill_pixel(0.3, -0.3), None);
+    }
+
+    #[test]
+    fn bubble_level_lights_the_downhill_pixel() {
+        let (mut cp, mock) = mock_board();
+
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(5.0, 0.0, 8.0));
+        assert_eq!(cp.bubble_level().unwrap(), Some(2));
+        let mut expected = vec![Color::BLACK; 10];
+        expected[2] = Color::GREEN;
+        assert_eq!(cp.pixels, expected);
+
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(0.0, 0.0, 9.8));
+        assert_eq!(cp.bubble_level().unwrap(), None);
+        assert_eq!(cp.pixels, vec![Color::GREEN; 10]);
+    }
+
+    #[test]
+    fn snapshot_to_json_writes_every_reading() {
+        let snapshot = Snapshot { temperature_c: 24.5, light: 700, sound: 12, accel: (0.0, -0.5, 9.8) };
+        assert_eq!(snapshot.to_json(), r#"{"temperature_c":24.5,"light":700,"sound":12,"accel":[0,-0.5,9.8]}"#);
+
+        let snapshot = Snapshot { temperature_c: f64::NAN, ..snapshot };
+        assert_eq!(snapshot.to_json(), r#"{"temperature_c":null,"light":700,"sound":12,"accel":[0,-0.5,9.8]}"#);
+
+        let snapshot = Snapshot { accel: (f32::INFINITY, f32::NEG_INFINITY, f32::NAN), ..snapshot };
+        assert_eq!(snapshot.to_json(), r#"{"temperature_c":null,"light":700,"sound":12,"accel":[null,null,null]}"#);
+    }
+
+    #[test]
+    fn start_tone_plays_until_stopped_without_waiting() {
+        let (mut cp, mock) = mock_board();
+
+        let started = Instant::now();
+        cp.start_tone(440).unwrap();
+        assert!(started.elapsed() < Duration::from_millis(100));
+
+        // A duration of 0 keeps the tone going.
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_TONE, 56, 3, 0, 0, END_SYSEX]);
+    }
+
+    #[test]
+    fn send_raw_frames_any_sub_command() {
+        let (mut cp, mock) = mock_board();
+
+        cp.send_raw(0x55, &[1, 2, 0x7F]).unwrap();
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, 0x55, 1, 2, 0x7F, END_SYSEX]);
+
+        // Top bits that would end the sysex early are dropped.
+        cp.send_raw(0xD5, &[0x83]).unwrap();
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, 0x55, 3, END_SYSEX]);
+    }
+
+    #[test]
+    fn wait_for_skips_everything_before_the_reply() {
+        let (mut cp, mock) = mock_board();
+        let pressed = Arc::new(AtomicBool::new(false));
+        let recorded = pressed.clone();
+        cp.on_button_a(move |state| recorded.store(state, Ordering::SeqCst)).unwrap();
+        mock.take_written();
+
+        let mut reply = vec![ANALOG_MESSAGE | LIGHT_PIN, 1, 2, DIGITAL_MESSAGE, 0x00, 0, DIGITAL_MESSAGE, 0x10, 0];
+        reply.extend(&[START_SYSEX, REPORT_FIRMWARE, 2, 5, END_SYSEX]);
+        reply.extend(&[START_SYSEX, CP_COMMAND, CP_CAP_REPLY, 3, 1, 0, 0, 0, 0, END_SYSEX]);
+        reply.extend(&[START_SYSEX, CP_COMMAND, CP_IMPL_VERS_REPLY, 1, 2, 3, END_SYSEX]);
+        mock.queue_reply(&reply);
+
+        assert_eq!(cp.wait_for(CP_IMPL_VERS_REPLY, Duration::from_millis(100)).unwrap(), vec![1, 2, 3]);
+        // Button reports on the way are still passed to the callbacks.
+        assert!(pressed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn debug_and_display_name_the_port() {
+        let (mut cp, mock) = mock_board();
+        assert_eq!(cp.to_string(), "Circuit Playground");
+
+        cp.builder = Some(CircuitPlaygroundBuilder::new().port("/dev/ttyACM0"));
+        mock.queue_cp_reply(CP_IMPL_VERS_REPLY, &[1, 0, 2]);
+        cp.implementation_version().unwrap();
+
+        assert!(format!("{:?}", cp).contains("\"/dev/ttyACM0\""));
+        assert_eq!(cp.to_string(), "Circuit Playground on /dev/ttyACM0 (implementation 1.0.2)");
+    }
+
+    #[test]
+    fn full_white_draws_the_documented_maximum() {
+        let (cp, _mock) = mock_board();
+
+        assert_eq!(cp.estimate_current_ma(&[Color::WHITE; 10]), 600);
+        assert_eq!(cp.estimate_current_ma(&[Color::BLACK; 10]), 0);
+        assert_eq!(cp.estimate_current_ma(&[Color::new(255, 0, 0)]), 20);
+    }
+
+    #[test]
+    fn set_pixels_limited_scales_every_color_alike() {
+        let (mut cp, _mock) = mock_board();
+        let colors = [Color::new(200, 100, 0); 10];
+
+        // The colors draw 235 mA, so a 47 mA budget scales them to a fifth.
+        cp.set_pixels_limited(&colors, 47).unwrap();
+        assert_eq!(cp.pixels, vec![Color::new(40, 20, 0); 10]);
+        assert!(cp.estimate_current_ma(&cp.pixels) <= 47);
+
+        cp.set_pixels_limited(&colors, 600).unwrap();
+        assert_eq!(cp.pixels, colors.to_vec());
+    }
+
+    // A mock that can be unplugged, after which reading and writing fail.
+    struct Pluggable {
+        mock: MockTransport,
+        unplugged: Arc<AtomicBool>,
+    }
+
+    impl Read for Pluggable {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.unplugged.load(Ordering::SeqCst) {
+                true => Err(io::ErrorKind::BrokenPipe.into()),
+                false => self.mock.read(buf),
+            }
+        }
+    }
+
+    impl Write for Pluggable {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match self.unplugged.load(Ordering::SeqCst) {
+                true => Err(io::ErrorKind::BrokenPipe.into()),
+                false => self.mock.write(buf),
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reconnecting_sends_the_cached_settings_again() {
+        let unplugged = Arc::new(AtomicBool::new(false));
+        let mut cp = CircuitPlayground::with_transport(Pluggable { mock: MockTransport::new(), unplugged: unplugged.clone() });
+        cp.set_accel_range(AccelRange::G8).unwrap();
+        cp.set_brightness(Brightness::new(40).unwrap()).unwrap();
+
+        unplugged.store(true, Ordering::SeqCst);
+        assert!(cp.set_accel_range(AccelRange::G2).is_err());
+        assert!(!cp.is_connected());
+        assert!(matches!(cp.reconnect(), Err(CpError::NoPort)));
+
+        let replacement = MockTransport::new();
+        replacement.queue_reply(&[START_SYSEX, REPORT_FIRMWARE, 2, 5, END_SYSEX]);
+        let (board, connected) = transport_board(replacement.clone());
+        cp.restore(board, connected).unwrap();
+
+        assert!(cp.is_connected());
+        assert_eq!(replacement.take_written(), vec![
+            SYSTEM_RESET, START_SYSEX, REPORT_FIRMWARE, END_SYSEX,
+            START_SYSEX, CP_COMMAND, CP_PIXEL_BRIGHTNESS, 40, END_SYSEX,
+            START_SYSEX, CP_COMMAND, CP_ACCEL_RANGE, 2, END_SYSEX,
+        ]);
+    }
+
+    #[test]
+    fn read_all_cap_reads_every_pad() {
+        let (mut cp, mock) = mock_board();
+
+        for &pin in CAP_PINS.iter() {
+            mock.queue_cp_reply(CP_CAP_REPLY, &cap_data(pin, pin as i32 * 100));
+        }
+        let readings = cp.read_all_cap().unwrap();
+
+        let expected: BTreeMap<u8, i32> = CAP_PINS.iter().map(|&pin| (pin, pin as i32 * 100)).collect();
+        assert_eq!(readings, expected);
+        assert_eq!(sub_commands(&mock.take_written()), vec![CP_CAP_READ; CAP_PINS.len()]);
+    }
+
+    #[test]
+    fn self_test_passes_a_board_that_answers() {
+        let (mut cp, mock) = mock_board();
+
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(0.1, -0.2, 9.8));
+        mock.queue_reply(&[ANALOG_MESSAGE | THERM_PIN, 0, 4]);
+        mock.queue_reply(&[ANALOG_MESSAGE | LIGHT_PIN, 60, 5]);
+        mock.queue_cp_reply(CP_IMPL_VERS_REPLY, &[1, 0, 0]);
+
+        let report = cp.self_test().unwrap();
+        assert!(report.passed(), "{:?}", report);
+    }
+
+    #[test]
+    fn self_test_fails_parts_that_do_not_answer() {
+        let (mut cp, mock) = mock_board();
+        cp.set_reply_timeout(Duration::from_millis(20));
+
+        // A board in free fall reads about 0g.
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(0.0, 0.0, 0.5));
+        let report = cp.self_test().unwrap();
+
+        assert!(report.pixels && report.speaker);
+        assert!(!report.accelerometer && !report.temperature && !report.light && !report.firmware);
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn stack_framing_matches_vec_framing() {
+        let (mut cp, mock) = mock_board();
+
+        // Up to CP_COMMAND_STACK_DATA_LEN bytes are framed on the stack, and
+        // longer commands fall back to a `Vec`.
+        for &len in [0, 1, 5, CP_COMMAND_STACK_DATA_LEN, CP_COMMAND_STACK_DATA_LEN + 1, 64].iter() {
+            let data: Vec<u8> = (0..len as u8).map(|i| i.wrapping_mul(37)).collect();
+            cp.send_raw(0x30, &data).unwrap();
+
+            let mut expected = vec![];
+            frame_cp_command(&mut expected, 0x30, &data);
+            assert_eq!(mock.take_written(), expected, "{} data bytes", len);
+        }
+    }
+
+    #[test]
+    fn brightness_levels_are_checked_on_creation() {
+        assert_eq!(Brightness::new(0).unwrap(), Brightness::OFF);
+        assert_eq!(Brightness::new(100).unwrap(), Brightness::MAX);
+        assert!(matches!(Brightness::new(101), Err(CpError::InvalidBrightness(101))));
+
+        assert_eq!(Brightness::from_fraction(0.425).level(), 43);
+        assert_eq!(Brightness::from_fraction(-1.0), Brightness::OFF);
+        assert_eq!(Brightness::from_fraction(2.0), Brightness::MAX);
+    }
+
+    #[test]
+    fn ir_is_unsupported_after_the_version_check() {
+        let (mut cp, mock) = mock_board();
+
+        mock.queue_cp_reply(CP_IMPL_VERS_REPLY, &[1, 0, 0]);
+        assert!(matches!(cp.ir_send(0x20DF10EF), Err(CpError::Unsupported("IR"))));
+        assert!(matches!(cp.ir_receive(), Err(CpError::Unsupported("IR"))));
+        assert_eq!(CpError::Unsupported("IR").to_string(), "the firmware doesn't support IR");
+
+        // The version was asked for once and nothing else was sent.
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_IMPL_VERS, END_SYSEX]);
+    }
+
+    #[test]
+    fn ir_returns_the_version_check_error() {
+        let (mut cp, _mock) = mock_board();
+        cp.set_reply_timeout(Duration::from_millis(20));
+
+        assert!(matches!(cp.ir_receive(), Err(CpError::Timeout)));
+    }
+
+    #[test]
+    fn ring_helpers_need_ten_pixels() {
+        let (mut cp, mock) = mock_board();
+        CircuitPlaygroundBuilder::new().pixel_count(12).configure(&mut cp);
+
+        assert!(matches!(cp.bubble_level(), Err(CpError::NotARing(12))));
+        assert!(matches!(cp.marble(Color::RED), Err(CpError::NotARing(12))));
+        assert!(matches!(cp.tilt_arrow(Color::RED), Err(CpError::NotARing(12))));
+        assert!(matches!(cp.touch_pixels(), Err(CpError::NotARing(12))));
+        assert!(mock.written().is_empty());
+        assert_eq!(CpError::NotARing(12).to_string(), "this follows the ring of 10 pixels, but the board has 12");
+    }
+
+    #[test]
+    fn dropping_a_disconnected_board_sends_nothing() {
+        let unplugged = Arc::new(AtomicBool::new(false));
+        let mock = MockTransport::new();
+        let mut cp = CircuitPlayground::with_transport(Pluggable { mock: mock.clone(), unplugged: unplugged.clone() });
+
+        unplugged.store(true, Ordering::SeqCst);
+        assert!(cp.show_pixels().is_err());
+        assert!(!cp.is_connected());
+
+        // Even if writes would get through again, a board noticed to be gone
+        // is not written to on the way out.
+        unplugged.store(false, Ordering::SeqCst);
+        drop(cp);
+        assert!(mock.written().is_empty());
+    }
+
+    #[test]
+    fn comet_tail_dims_behind_the_head() {
+        let (mut cp, _mock) = mock_board();
+
+        cp.comet(Color::new(240, 120, 60), 3, 3, 0).unwrap();
+
+        // After three frames the head is on pixel 2 and the tail wraps
+        // round past pixel 0.
+        let mut expected = vec![Color::BLACK; 10];
+        expected[2] = Color::new(240, 120, 60);
+        expected[1] = Color::new(120, 60, 30);
+        expected[0] = Color::new(80, 40, 20);
+        expected[9] = Color::new(60, 30, 15);
+        assert_eq!(cp.pixels, expected);
+
+        cp.comet(Color::WHITE, 20, 1, 0).unwrap();
+        assert!(cp.pixels.iter().all(|&color| color != Color::BLACK));
+    }
+
+    #[test]
+    fn fade_to_follows_the_easing_curve() {
+        let (mut cp, mock) = mock_board();
+
+        cp.fade_to(Color::RED, 0, 2, Easing::EaseIn).unwrap();
+
+        // Halfway through an ease-in is an eighth of the way to red, 32.
+        let written = mock.take_written();
+        assert_eq!(&written[..9], &[START_SYSEX, CP_COMMAND, CP_PIXEL_SET, 0, 16, 0, 0, 0, END_SYSEX]);
+        assert_eq!(cp.pixels, vec![Color::RED; 10]);
+    }
+
+    #[test]
+    fn linear_fade_passes_through_the_average() {
+        let (mut cp, mock) = mock_board();
+        cp.fill(Color::new(0, 40, 200)).unwrap();
+        mock.take_written();
+
+        cp.fade_to(Color::new(100, 0, 0), 0, 2, Easing::Linear).unwrap();
+
+        let midpoint = Color::new(50, 20, 100).to_firmata_bytes();
+        let written = mock.take_written();
+        assert_eq!(&written[..9], &[START_SYSEX, CP_COMMAND, CP_PIXEL_SET, 0, midpoint[0], midpoint[1], midpoint[2], midpoint[3], END_SYSEX]);
+        assert_eq!(cp.pixels, vec![Color::new(100, 0, 0); 10]);
+    }
+
+    #[test]
+    fn marble_rolls_to_three_o_clock_on_a_rightward_tilt() {
+        let (mut cp, mock) = mock_board();
+
+        // Pixel 7 sits at 90 degrees, three o'clock with the USB port at the
+        // top, and the marble takes the short way there from pixel 0.
+        let mut path = vec![];
+        for _ in 0..5 {
+            mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(-5.0, 0.0, 8.0));
+            path.push(cp.marble(Color::BLUE).unwrap());
+        }
+        assert_eq!(path, vec![9, 8, 7, 7, 7]);
+        assert_eq!(cp.pixels[7], Color::BLUE);
+
+        // On a level board it stays put.
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(0.0, 0.0, 9.8));
+        assert_eq!(cp.marble(Color::BLUE).unwrap(), 7);
+    }
+
+    #[test]
+    fn read_accelerometer_avg_averages_each_axis() {
+        let (mut cp, mock) = mock_board();
+
+        for &(x, y, z) in [(1.0, -2.0, 9.0), (3.0, 0.0, 10.0), (2.0, -1.0, 9.5), (-2.0, -1.0, 9.5)].iter() {
+            mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(x, y, z));
+        }
+        assert_eq!(cp.read_accelerometer_avg(4).unwrap(), (1.0, -1.0, 9.5));
+        assert_eq!(sub_commands(&mock.take_written()), vec![CP_ACCEL_READ; 4]);
+
+        // At least one reading is always taken.
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(0.5, 0.5, 0.5));
+        assert_eq!(cp.read_accelerometer_avg(0).unwrap(), (0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn tick_starts_and_stops_scheduled_notes() {
+        let (mut cp, mock) = mock_board();
+        let tone = |hz: u16| vec![START_SYSEX, CP_COMMAND, CP_TONE, (hz & 0x7F) as u8, (hz >> 7) as u8, 0, 0, END_SYSEX];
+        let stop = vec![START_SYSEX, CP_COMMAND, CP_NO_TONE, END_SYSEX];
+
+        cp.schedule_note(Note::C4, 0, 100);
+        cp.schedule_note(Note::G4, 50, 100);
+        cp.schedule_note(Note::C4, 200, 50);
+
+        // G4 takes over from C4, and C4 ending underneath it changes nothing.
+        let expected = [
+            (0, tone(262), true),
+            (20, vec![], true),
+            (60, tone(392), true),
+            (120, vec![], true),
+            (160, stop.clone(), true),
+            (210, tone(262), true),
+            (260, stop, false),
+        ];
+        for (now, written, more) in expected.iter() {
+            assert_eq!(cp.tick(*now).unwrap(), *more, "at {} ms", now);
+            assert_eq!(&mock.take_written(), written, "at {} ms", now);
+        }
+    }
+
+    #[test]
+    fn tilt_arrow_points_down_a_45_degree_tilt() {
+        let (mut cp, mock) = mock_board();
+
+        // Tilted 45 degrees towards pixel 8, at 60 degrees round the ring,
+        // gravity pulls 6.9 m/s^2 sideways.
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(-6.0, 3.46, 6.9));
+        assert_eq!(cp.tilt_arrow(Color::WHITE).unwrap(), Some(8));
+
+        assert_eq!(cp.pixels[8], Color::WHITE);
+        for &side in [7, 9].iter() {
+            assert!(cp.pixels[side] != Color::BLACK && cp.pixels[side].r < 255, "pixel {}", side);
+        }
+        // The uphill half of the ring is off.
+        assert!([0, 1, 2, 3, 4].iter().all(|&pixel| cp.pixels[pixel] == Color::BLACK));
+    }
+
+    #[test]
+    fn tilt_arrow_lights_nothing_inside_the_dead_zone() {
+        let (mut cp, mock) = mock_board();
+
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(-0.5, 0.5, 9.8));
+        assert_eq!(cp.tilt_arrow(Color::WHITE).unwrap(), None);
+        assert_eq!(cp.pixels, vec![Color::BLACK; 10]);
+
+        cp.set_tilt_dead_zone(0.5);
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel_data(-0.5, 0.5, 9.8));
+        assert!(cp.tilt_arrow(Color::WHITE).unwrap().is_some());
+    }
+
+    #[test]
+    fn map_range_rescales_and_inverts() {
+        assert_eq!(map_range(512.0, 0.0, 1024.0, 0.0, 100.0), 50.0);
+        assert_eq!(map_range(256.0, 0.0, 1024.0, 100.0, 0.0), 75.0);
+        assert_eq!(map_range(5.0, 10.0, 0.0, 0.0, 1.0), 0.5);
+        assert_eq!(map_range(3.0, 2.0, 2.0, 7.0, 9.0), 7.0);
+
+        // Values outside the input range land outside the output range.
+        assert_eq!(map_range(2048.0, 0.0, 1024.0, 0.0, 100.0), 200.0);
+    }
+
+    #[test]
+    fn map_range_clamped_stays_inside_the_output_range() {
+        assert_eq!(map_range_clamped(2048.0, 0.0, 1024.0, 0.0, 100.0), 100.0);
+        assert_eq!(map_range_clamped(-10.0, 0.0, 1024.0, 0.0, 100.0), 0.0);
+        assert_eq!(map_range_clamped(-10.0, 0.0, 1024.0, 100.0, 0.0), 100.0);
+        assert_eq!(map_range_clamped(512.0, 0.0, 1024.0, 100.0, 0.0), 50.0);
+    }
+
+    #[test]
+    fn touching_pad_3_lights_pixel_0() {
+        let (mut cp, mock) = mock_board();
+
+        for &pin in CAP_PINS.iter() {
+            let value = if pin == 3 { 900 } else { 50 };
+            mock.queue_cp_reply(CP_CAP_REPLY, &cap_data(pin, value));
+        }
+        assert_eq!(cp.touch_pixels().unwrap(), vec![3]);
+
+        // Pad 3 comes first in CAP_PAD_PIXELS, so it gets the wheel's first color.
+        let mut expected = vec![Color::BLACK; 10];
+        expected[0] = color_wheel(0);
+        assert_eq!(cp.pixels, expected);
+    }
+
+    // A mock whose writes fail with each of `failures` in turn before they
+    // start going through.
+    struct Flaky {
+        mock: MockTransport,
+        failures: Arc<std::sync::Mutex<Vec<io::ErrorKind>>>,
+    }
+
+    impl Read for Flaky {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.mock.read(buf)
+        }
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut failures = self.failures.lock().unwrap();
+            match failures.is_empty() {
+                true => self.mock.write(buf),
+                false => Err(failures.remove(0).into()),
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Returns a board whose writes fail with `failures` first.
+    fn flaky_board(failures: &[io::ErrorKind]) -> (CircuitPlayground, MockTransport) {
+        let mock = MockTransport::new();
+        let failures = Arc::new(std::sync::Mutex::new(failures.to_vec()));
+        (CircuitPlayground::with_transport(Flaky { mock: mock.clone(), failures }), mock)
+    }
+
+    #[test]
+    fn commands_are_retried_after_transient_errors() {
+        let (mut cp, mock) = flaky_board(&[io::ErrorKind::WouldBlock, io::ErrorKind::Interrupted]);
+
+        cp.show_pixels().unwrap();
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_PIXEL_SHOW, END_SYSEX]);
+        assert!(cp.is_connected());
+    }
+
+    #[test]
+    fn write_errors_are_returned_once_retries_run_out() {
+        let (mut cp, mock) = flaky_board(&[io::ErrorKind::WouldBlock; 3]);
+        assert!(matches!(cp.show_pixels(), Err(CpError::Io(ref err)) if err.kind() == io::ErrorKind::WouldBlock));
+        assert!(mock.take_written().is_empty());
+
+        // Other errors aren't retried at all.
+        let (mut cp, mock) = flaky_board(&[io::ErrorKind::BrokenPipe]);
+        cp.set_write_retries(5);
+        assert!(matches!(cp.show_pixels(), Err(CpError::Io(ref err)) if err.kind() == io::ErrorKind::BrokenPipe));
+        assert!(mock.take_written().is_empty());
+    }
+
+    #[test]
+    fn night_light_has_hysteresis_around_the_threshold() {
+        let mut lit = false;
+        let mut states = vec![];
+        for &level in [300, 190, 230, 250, 251, 240, 150].iter() {
+            lit = night_light_lit(lit, level, 200);
+            states.push(lit);
+        }
+
+        // Once on it stays on up to 50 above the threshold, and once off it
+        // stays off until the level is back below the threshold.
+        assert_eq!(states, vec![false, true, true, true, false, false, true]);
+    }
+
+    #[test]
+    fn night_light_turns_the_ring_on_in_the_dark() {
+        let (mut cp, mock) = mock_board();
+        for &level in [100u16, 120, 900].iter() {
+            mock.queue_reply(&[ANALOG_MESSAGE | LIGHT_PIN, (level & 0x7F) as u8, (level >> 7) as u8]);
+        }
+
+        let mut reads = 0;
+        cp.night_light_until(Color::RED, 200, || { reads += 1; reads > 3 }).unwrap();
+
+        // Clear, one brightness change and fill when it gets dark, a clear
+        // when it gets light again and a last one on the way out.
+        let subs = sub_commands(&mock.take_written());
+        let shown: Vec<u8> = subs.into_iter().filter(|&sub| sub != CP_PIXEL_SET).collect();
+        assert_eq!(shown, vec![
+            CP_PIXEL_CLEAR, CP_PIXEL_SHOW,
+            CP_PIXEL_BRIGHTNESS, CP_PIXEL_SHOW,
+            CP_PIXEL_CLEAR, CP_PIXEL_SHOW,
+            CP_PIXEL_CLEAR, CP_PIXEL_SHOW,
+        ]);
+        assert_eq!(cp.brightness, Some(NIGHT_LIGHT_BRIGHTNESS));
+    }
+
+    #[test]
+    fn stop_all_turns_everything_off() {
+        let (mut cp, mock) = mock_board();
+        cp.schedule_note(Note::A4, 0, 1000);
+
+        cp.stop_all().unwrap();
+
+        let mut expected = vec![CP_ACCEL_STREAM_OFF, CP_ACCEL_TAP_STREAM_OFF];
+        expected.extend(vec![CP_CAP_OFF; CAP_PINS.len()]);
+        expected.extend(&[CP_NO_TONE, CP_PIXEL_CLEAR, CP_PIXEL_SHOW]);
+        assert_eq!(sub_commands(&mock.take_written()), expected);
+
+        // Scheduled notes are dropped too.
+        assert!(!cp.tick(10).unwrap());
+    }
+
+    #[test]
+    fn stop_all_tries_every_command_before_failing() {
+        let (mut cp, mock) = flaky_board(&[io::ErrorKind::BrokenPipe]);
+
+        assert!(cp.stop_all().is_err());
+        assert_eq!(sub_commands(&mock.take_written()).len(), 12);
+    }
+
+    #[test]
+    fn buttons_pressed_reads_both_ports_in_one_round_trip() {
+        let (mut cp, mock) = mock_board();
+
+        // Button A is bit 4 of port 0 and button B bit 3 of port 2, and the
+        // reports can come in either order.
+        mock.queue_reply(&[DIGITAL_MESSAGE | 2, 0x08, 0, DIGITAL_MESSAGE, 0x10, 0]);
+        assert_eq!(cp.buttons_pressed().unwrap(), (true, true));
+        assert_eq!(mock.take_written(), vec![
+            PIN_MODE, BUTTON_A_PIN, INPUT, REPORT_DIGITAL, 1,
+            PIN_MODE, BUTTON_B_PIN, INPUT, REPORT_DIGITAL | 2, 1,
+            REPORT_DIGITAL, 0, REPORT_DIGITAL | 2, 0,
+        ]);
+
+        mock.queue_reply(&[DIGITAL_MESSAGE, 0x10, 0, DIGITAL_MESSAGE | 2, 0x20, 0]);
+        assert!(!cp.both_pressed().unwrap());
+    }
+
+    #[test]
+    fn set_pixel_sends_channels_in_the_color_order() {
+        let (mut cp, mock) = mock_board();
+        cp.set_color_order(ColorOrder::Grb);
+
+        cp.set_pixel(2, Color::RED).unwrap();
+        let swapped = Color::GREEN.to_firmata_bytes();
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_PIXEL_SET, 2, swapped[0], swapped[1], swapped[2], swapped[3], END_SYSEX]);
+
+        // The color is still remembered as it was set.
+        assert_eq!(cp.pixels[2], Color::RED);
+    }
+
+    #[test]
+    fn set_red_led_writes_pin_13() {
+        let (mut cp, mock) = mock_board();
+
+        // Pin 13 is the sixth bit of port 1.
+        cp.set_red_led(true).unwrap();
+        assert_eq!(mock.take_written(), vec![PIN_MODE, RED_LED_PIN, OUTPUT, DIGITAL_MESSAGE | 1, 0x20, 0]);
+
+        cp.set_red_led(false).unwrap();
+        assert_eq!(mock.take_written(), vec![PIN_MODE, RED_LED_PIN, OUTPUT, DIGITAL_MESSAGE | 1, 0, 0]);
+    }
+
+    #[test]
+    fn calibrate_cap_moves_the_touch_threshold_up_by_the_baseline() {
+        let (mut cp, mock) = mock_board();
+
+        mock.queue_cp_reply(CP_CAP_REPLY, &cap_data(6, 700));
+        assert!(cp.is_touched(6).unwrap());
+
+        for &value in [480, 520, 490, 510].iter() {
+            mock.queue_cp_reply(CP_CAP_REPLY, &cap_data(6, value));
+        }
+        assert_eq!(cp.calibrate_cap(6, 4).unwrap(), 500);
+
+        mock.queue_cp_reply(CP_CAP_REPLY, &cap_data(6, 700));
+        assert!(!cp.is_touched(6).unwrap());
+        mock.queue_cp_reply(CP_CAP_REPLY, &cap_data(6, 801));
+        assert!(cp.is_touched(6).unwrap());
+    }
+
+    #[test]
+    fn fill_gamma_sends_the_colors_through_the_gamma_table() {
+        let (mut cp, mock) = mock_board();
+        let color = Color::new(128, 64, 255);
+
+        cp.fill(color).unwrap();
+        let raw = mock.take_written();
+        cp.fill_gamma(color).unwrap();
+        let corrected = mock.take_written();
+
+        let table = gamma_table();
+        let expected = Color::new(table[128], table[64], table[255]);
+        assert_ne!(raw, corrected);
+        assert_eq!(cp.pixels, vec![expected; 10]);
+
+        let data = expected.to_firmata_bytes();
+        assert_eq!(&corrected[..9], &[START_SYSEX, CP_COMMAND, CP_PIXEL_SET, 0, data[0], data[1], data[2], data[3], END_SYSEX]);
+    }
+
+    #[test]
+    fn sweep_tone_steps_from_start_to_end() {
+        let (mut cp, mock) = mock_board();
+
+        cp.sweep_tone(200, 1000, 0, 5, SweepCurve::Linear).unwrap();
+        assert_eq!(tone_frequencies(&mock.take_written()), vec![200, 400, 600, 800, 1000]);
+
+        cp.sweep_tone(100, 1600, 0, 5, SweepCurve::Logarithmic).unwrap();
+        assert_eq!(tone_frequencies(&mock.take_written()), vec![100, 200, 400, 800, 1600]);
+    }
+
+    #[test]
+    fn sweep_tone_stays_within_the_hardware_range() {
+        let (mut cp, mock) = mock_board();
+
+        cp.sweep_tone(20000, 0, 0, 8, SweepCurve::Logarithmic).unwrap();
+        let frequencies = tone_frequencies(&mock.take_written());
+
+        assert_eq!(frequencies.len(), 8);
+        assert_eq!((frequencies[0], frequencies[7]), (0x3FFF, 1));
+        assert!(frequencies.windows(2).all(|pair| pair[0] >= pair[1]));
+    }
+
+    #[test]
+    fn play_melody_bpm_times_notes_by_the_tempo() {
+        let (mut cp, mock) = mock_board();
+
+        // A sixteenth at 6000 bpm lasts 2 ms.
+        cp.play_melody_bpm(&[(Note::A4, NoteValue::Sixteenth), (Note::A5, NoteValue::Eighth)], 6000).unwrap();
+
+        // A4 is 440 hz, 3 * 128 + 56, and A5 is 880 hz, 6 * 128 + 112.
+        assert_eq!(mock.take_written(), vec![
+            START_SYSEX, CP_COMMAND, CP_TONE, 56, 3, 2, 0, END_SYSEX,
+            START_SYSEX, CP_COMMAND, CP_TONE, 112, 6, 5, 0, END_SYSEX,
+        ]);
+    }
+
+    #[test]
+    fn read_light_smoothed_starts_over_when_the_window_changes() {
+        let (mut cp, mock) = mock_board();
+        let read = |cp: &mut CircuitPlayground, window, value: u16| {
+            mock.queue_reply(&[ANALOG_MESSAGE | LIGHT_PIN, (value & 0x7F) as u8, (value >> 7) as u8]);
+            cp.read_light_smoothed(window).unwrap()
+        };
+
+        assert_eq!(read(&mut cp, 2, 300), 300.0);
+        assert_eq!(read(&mut cp, 2, 500), 400.0);
+        assert_eq!(read(&mut cp, 2, 900), 700.0);
+        assert_eq!(read(&mut cp, 4, 100), 100.0);
+    }
+
+    #[test]
+    fn is_clap_needs_a_sudden_rise() {
+        let claps = |peaks: &[u16]| peaks.windows(2).filter(|pair| is_clap(pair[0], pair[1], 200)).count();
+
+        assert_eq!(claps(&[10, 20, 350, 40]), 1);
+        // A slow ramp and sustained noise never jump from below half the
+        // threshold to above it.
+        assert_eq!(claps(&[10, 60, 110, 160, 210, 260, 310]), 0);
+        assert_eq!(claps(&[300, 320, 310, 330]), 0);
+    }
+
+    #[test]
+    fn detect_clap_returns_true_on_a_jump_in_loudness() {
+        let (mut cp, mock) = mock_board();
+        let queue_peak = |low: u16, high: u16| {
+            let mut reply = vec![];
+            for &value in [low, high, low, high].iter() {
+                reply.extend(&[ANALOG_MESSAGE | SOUND_PIN, (value & 0x7F) as u8, (value >> 7) as u8]);
+            }
+            mock.queue_reply(&reply);
+        };
+
+        queue_peak(510, 520);
+        queue_peak(500, 530);
+        queue_peak(400, 700);
+        assert!(cp.detect_clap(150, Duration::from_secs(1)).unwrap());
+    }
+
+    #[test]
+    fn set_pixel_hsv_matches_set_pixel_with_the_converted_color() {
+        let (mut cp, mock) = mock_board();
+
+        cp.set_pixel_hsv(0, 0.0, 1.0, 1.0).unwrap();
+        let hsv = mock.take_written();
+        cp.set_pixel(0, Color::RED).unwrap();
+        assert_eq!(hsv, mock.take_written());
+
+        cp.set_pixel_hsv(3, 240.0, 1.0, 0.5).unwrap();
+        assert_eq!(cp.pixels[3], Color::from_hsv(240.0, 1.0, 0.5));
+        assert!(matches!(cp.set_pixel_hsv(10, 0.0, 1.0, 1.0), Err(CpError::InvalidPixel(10))));
+    }
+
+    #[test]
+    fn theater_chase_lights_each_pixel_once_every_three_frames() {
+        let (mut cp, mock) = mock_board();
+
+        cp.theater_chase(Color::BLUE, 3, 0).unwrap();
+
+        let blue = Color::BLUE.to_firmata_bytes();
+        let lit: Vec<u8> = mock.take_written().windows(8)
+            .filter(|window| window[..3] == [START_SYSEX, CP_COMMAND, CP_PIXEL_SET] && window[4..] == blue)
+            .map(|window| window[3])
+            .collect();
+
+        let mut sorted = lit.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..10).collect::<Vec<u8>>());
+        assert_eq!(&lit[..4], &[0, 3, 6, 9]);
+    }
+
+    #[test]
+    fn theater_chase_until_stops_when_asked() {
+        let (mut cp, mock) = mock_board();
+        let mut frames = 0;
+
+        cp.theater_chase_until(Color::BLUE, 0, || { frames += 1; frames > 2 }).unwrap();
+
+        let shows = sub_commands(&mock.take_written()).into_iter().filter(|&sub| sub == CP_PIXEL_SHOW).count();
+        assert_eq!(shows, 2);
+    }
+
+    #[test]
+    fn reset_waits_for_the_firmware_report() {
+        let (mut cp, mock) = mock_board();
+
+        // Replies that aren't the firmware report are skipped over.
+        mock.queue_cp_reply(CP_CAP_REPLY, &cap_data(3, 10));
+        mock.queue_reply(&[START_SYSEX, REPORT_FIRMWARE, 2, 5, b'C', 0, b'P', 0, END_SYSEX]);
+        cp.reset().unwrap();
+        assert_eq!(mock.take_written(), vec![SYSTEM_RESET, START_SYSEX, REPORT_FIRMWARE, END_SYSEX]);
+
+        cp.set_reply_timeout(Duration::from_millis(20));
+        assert!(matches!(cp.reset(), Err(CpError::Timeout)));
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use firmata::{END_SYSEX, START_SYSEX};
+
+use crate::CP_COMMAND;
+
+// The state shared between a `MockTransport` and its clones.
+#[derive(Debug, Default)]
+struct MockState {
+    replies: VecDeque<Vec<u8>>,
+    written: Vec<u8>,
+}
+
+/// A `Transport` that doesn't need a board, for testing code that uses a
+/// `CircuitPlayground`. Reads return the replies queued with `queue_reply`,
+/// and everything written is recorded.
+///
+/// Each read returns at most one queued reply, as if the board sent them one
+/// at a time, so replies can be queued for several queries in a row.
+///
+/// Clones share the same state, so keep a clone to inspect the transport
+/// after passing it to `CircuitPlayground::with_transport`.
+#[derive(Debug, Clone, Default)]
+pub struct MockTransport {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockTransport {
+    /// Creates a new `MockTransport` with no queued replies.
+    pub fn new() -> MockTransport {
+        MockTransport::default()
+    }
+
+    /// Queues `bytes` to be returned by later reads, after any replies that
+    /// are already queued.
+    pub fn queue_reply(&self, bytes: &[u8]) {
+        if !bytes.is_empty() {
+            self.lock().replies.push_back(bytes.to_vec());
+        }
+    }
+
+    /// Queues a Circuit Playground reply carrying the sub-command `sub` and
+    /// `data`, which should already be 7-bit encoded.
+    pub fn queue_cp_reply(&self, sub: u8, data: &[u8]) {
+        let mut reply = vec![START_SYSEX, CP_COMMAND, sub];
+        reply.extend_from_slice(data);
+        reply.push(END_SYSEX);
+        self.queue_reply(&reply);
+    }
+
+    /// Returns every byte written so far.
+    pub fn written(&self) -> Vec<u8> {
+        self.lock().written.clone()
+    }
+
+    /// Returns every byte written so far and forgets them.
+    pub fn take_written(&self) -> Vec<u8> {
+        let mut state = self.lock();
+        state.written.split_off(0)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        // A panic while holding the lock can't leave the state half changed,
+        // so a poisoned lock is still safe to use.
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl io::Read for MockTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.lock();
+        let reply = match state.replies.front_mut() {
+            Some(reply) => reply,
+            None => return Ok(0),
+        };
+
+        // Whatever doesn't fit in `buf` is left for the next read.
+        let len = buf.len().min(reply.len());
+        buf[..len].copy_from_slice(&reply[..len]);
+        reply.drain(..len);
+
+        if reply.is_empty() {
+            state.replies.pop_front();
+        }
+        Ok(len)
+    }
+}
+
+impl io::Write for MockTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use firmata::{END_SYSEX, START_SYSEX};
+
+    use super::MockTransport;
+    use crate::protocol::encode_7bit_float;
+    use crate::{CircuitPlayground, CP_ACCEL_READ, CP_ACCEL_READ_REPLY, CP_COMMAND};
+
+    #[test]
+    fn read_accelerometer_round_trip() {
+        let mock = MockTransport::new();
+        let mut cp = CircuitPlayground::with_transport(mock.clone());
+
+        let data: Vec<u8> = [0.5f32, -1.25, 9.75].iter().flat_map(|&value| encode_7bit_float(value).to_vec()).collect();
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &data);
+
+        assert_eq!(cp.read_accelerometer().unwrap(), (0.5, -1.25, 9.75));
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_ACCEL_READ, END_SYSEX]);
+    }
+
+    #[test]
+    fn reads_return_one_reply_at_a_time() {
+        let mut mock = MockTransport::new();
+        mock.queue_reply(&[1, 2, 3]);
+        mock.queue_reply(&[4]);
+
+        let mut buf = [0; 2];
+        assert_eq!(mock.read(&mut buf).unwrap(), 2);
+        assert_eq!(buf, [1, 2]);
+        assert_eq!(mock.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 3);
+        assert_eq!(mock.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 4);
+        assert_eq!(mock.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn clones_share_replies_and_writes() {
+        let mock = MockTransport::new();
+        let mut clone = mock.clone();
+
+        mock.queue_reply(&[1, 2, 3]);
+        clone.write_all(&[4, 5]).unwrap();
+
+        let mut buf = [0; 8];
+        assert_eq!(clone.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], &[1, 2, 3]);
+        assert_eq!(clone.read(&mut buf).unwrap(), 0);
+        assert_eq!(mock.written(), vec![4, 5]);
+        assert_eq!(mock.take_written(), vec![4, 5]);
+        assert!(mock.written().is_empty());
+    }
+}
@@ -0,0 +1,100 @@
+/// Musical notes that can be played on the speaker, from C4 (middle C) to B5.
+/// Sharps are written with an `S`, so `CS4` is C#4. `Rest` is silence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Note {
+    C4, CS4, D4, DS4, E4, F4, FS4, G4, GS4, A4, AS4, B4,
+    C5, CS5, D5, DS5, E5, F5, FS5, G5, GS5, A5, AS5, B5,
+    Rest,
+}
+
+impl Note {
+    /// Returns the equal-tempered frequency of the note in hz, rounded to the
+    /// nearest whole number (A4 is 440 hz). A `Rest` has a frequency of 0.
+    pub fn frequency(&self) -> u16 {
+        match *self {
+            Note::C4  => 262,
+            Note::CS4 => 277,
+            Note::D4  => 294,
+            Note::DS4 => 311,
+            Note::E4  => 330,
+            Note::F4  => 349,
+            Note::FS4 => 370,
+            Note::G4  => 392,
+            Note::GS4 => 415,
+            Note::A4  => 440,
+            Note::AS4 => 466,
+            Note::B4  => 494,
+            Note::C5  => 523,
+            Note::CS5 => 554,
+            Note::D5  => 587,
+            Note::DS5 => 622,
+            Note::E5  => 659,
+            Note::F5  => 698,
+            Note::FS5 => 740,
+            Note::G5  => 784,
+            Note::GS5 => 831,
+            Note::A5  => 880,
+            Note::AS5 => 932,
+            Note::B5  => 988,
+            Note::Rest => 0,
+        }
+    }
+}
+
+/// How long a note lasts relative to the beat, which is a quarter note.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteValue {
+    Whole,
+    Half,
+    Quarter,
+    Eighth,
+    Sixteenth,
+}
+
+impl NoteValue {
+    /// Returns how many milliseconds the note lasts at a tempo of `bpm`
+    /// quarter notes per minute, so a quarter note at 120 bpm lasts 500 ms.
+    /// A tempo of 0 is treated as 1 bpm.
+    pub fn duration_ms(&self, bpm: u16) -> u32 {
+        let quarter_ms = 60_000 / bpm.max(1) as u32;
+
+        match *self {
+            NoteValue::Whole     => quarter_ms * 4,
+            NoteValue::Half      => quarter_ms * 2,
+            NoteValue::Quarter   => quarter_ms,
+            NoteValue::Eighth    => quarter_ms / 2,
+            NoteValue::Sixteenth => quarter_ms / 4,
+        }
+    }
+}
+
+// A note queued with `CircuitPlayground::schedule_note`, sounding from
+// `start_ms` until just before `end_ms` on the clock passed to `tick`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ScheduledNote {
+    pub(crate) note: Note,
+    pub(crate) start_ms: u64,
+    pub(crate) end_ms: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notes_map_to_equal_tempered_frequencies() {
+        assert_eq!(Note::A4.frequency(), 440);
+        assert_eq!(Note::C4.frequency(), 262);
+        assert_eq!(Note::A5.frequency(), 880);
+        assert_eq!(Note::Rest.frequency(), 0);
+    }
+
+    #[test]
+    fn note_values_divide_the_beat() {
+        assert_eq!(NoteValue::Quarter.duration_ms(120), 500);
+        assert_eq!(NoteValue::Whole.duration_ms(120), 2000);
+        assert_eq!(NoteValue::Eighth.duration_ms(120), 250);
+        assert_eq!(NoteValue::Sixteenth.duration_ms(60), 250);
+        assert_eq!(NoteValue::Half.duration_ms(0), 120_000);
+    }
+}
@@ -0,0 +1,104 @@
+use std::fs;
+use std::path::Path;
+
+/// The USB vendor ID used by Adafruit boards.
+pub const ADAFRUIT_VID: u16 = 0x239A;
+
+/// USB product IDs of Circuit Playground boards running an Arduino sketch
+/// such as the Firmata firmware: the Circuit Playground Classic and the
+/// Circuit Playground Express.
+pub const CIRCUIT_PLAYGROUND_PIDS: [u16; 2] = [0x8011, 0x8018];
+
+/// A structure representing a serial port found on the system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortInfo {
+    /// The name to pass to `CircuitPlayground::new`.
+    pub name: String,
+    /// The USB vendor ID, if the port belongs to a USB device.
+    pub vid: Option<u16>,
+    /// The USB product ID, if the port belongs to a USB device.
+    pub pid: Option<u16>,
+}
+
+impl PortInfo {
+    /// Returns true if the port belongs to a Circuit Playground.
+    pub fn is_circuit_playground(&self) -> bool {
+        match (self.vid, self.pid) {
+            (Some(vid), Some(pid)) => vid == ADAFRUIT_VID && CIRCUIT_PLAYGROUND_PIDS.contains(&pid),
+            _ => false,
+        }
+    }
+}
+
+/// Returns the serial ports on the system along with their USB IDs. Ports
+/// are found through sysfs, so this only finds ports on Linux and returns
+/// an empty list elsewhere.
+pub fn list_ports() -> Vec<PortInfo> {
+    let entries = match fs::read_dir("/sys/class/tty") {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    let mut ports: Vec<PortInfo> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().join("device").exists())
+        .map(|entry| {
+            let usb_device = entry.path().join("device").join("..");
+            PortInfo {
+                name: format!("/dev/{}", entry.file_name().to_string_lossy()),
+                vid: read_hex_id(&usb_device.join("idVendor")),
+                pid: read_hex_id(&usb_device.join("idProduct")),
+            }
+        })
+        .collect();
+
+    ports.sort_by(|a, b| a.name.cmp(&b.name));
+    ports
+}
+
+/// Returns the first port in `ports` that belongs to a Circuit Playground.
+pub fn find_circuit_playground(ports: &[PortInfo]) -> Option<&PortInfo> {
+    ports.iter().find(|port| port.is_circuit_playground())
+}
+
+// Reads a USB ID written in hex, as found in the idVendor and idProduct files.
+fn read_hex_id(path: &Path) -> Option<u16> {
+    let contents = fs::read_to_string(path).ok()?;
+    u16::from_str_radix(contents.trim(), 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port(name: &str, vid: Option<u16>, pid: Option<u16>) -> PortInfo {
+        PortInfo { name: name.to_string(), vid, pid }
+    }
+
+    #[test]
+    fn finds_the_first_circuit_playground() {
+        let ports = [
+            port("/dev/ttyS0", None, None),
+            port("/dev/ttyACM0", Some(0x2341), Some(0x0043)),
+            port("/dev/ttyACM1", Some(ADAFRUIT_VID), Some(0x800B)),
+            port("/dev/ttyACM2", Some(ADAFRUIT_VID), Some(0x8018)),
+            port("/dev/ttyACM3", Some(ADAFRUIT_VID), Some(0x8011)),
+        ];
+
+        assert_eq!(find_circuit_playground(&ports).map(|port| port.name.as_str()), Some("/dev/ttyACM2"));
+        assert_eq!(find_circuit_playground(&ports[..3]), None);
+    }
+
+    #[test]
+    fn reads_usb_ids_written_in_hex() {
+        let path = std::env::temp_dir().join(format!("cp-firmata-id-{}", std::process::id()));
+
+        fs::write(&path, "239a\n").unwrap();
+        assert_eq!(read_hex_id(&path), Some(ADAFRUIT_VID));
+        fs::write(&path, "not hex").unwrap();
+        assert_eq!(read_hex_id(&path), None);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read_hex_id(&path), None);
+    }
+}
@@ -0,0 +1,231 @@
+//! Helpers for the 7-bit encoding used by Circuit Playground Firmata replies.
+//!
+//! Firmata can only send 7 bits of data per byte, so multi-byte values are
+//! sent one 8-bit byte at a time, with each byte split across two Firmata
+//! bytes: the low 7 bits first, then the high bit. Multi-byte values are
+//! little-endian.
+//!
+//! Each reply packs its values differently:
+//!
+//! - Accelerometer readings (`CP_ACCEL_READ_REPLY`) are three little-endian
+//!   IEEE-754 `f32`s for x, y and z, 8 Firmata bytes each.
+//! - Tap readings (`CP_ACCEL_TAP_REPLY`) are the accelerometer's click
+//!   register as one split 8-bit byte.
+//! - Cap touch readings (`CP_CAP_REPLY`) are the pin number as a plain 7-bit
+//!   byte, then the reading as a little-endian `i32` in 8 Firmata bytes.
+//! - Sensed colors (`CP_SENSECOLOR_REPLY`) are the red, green and blue
+//!   channels as three split 8-bit bytes.
+//! - Implementation versions (`CP_IMPL_VERS_REPLY`) are the major, minor and
+//!   bugfix numbers as plain 7-bit bytes.
+
+/// Reassembles one 8-bit byte from the first two bytes of `bytes`.
+///
+/// # Panics
+///
+/// Panics if `bytes` is shorter than two bytes.
+pub fn decode_7bit_byte(bytes: &[u8]) -> u8 {
+    (bytes[0] & 0x7F) | ((bytes[1] & 0x01) << 7)
+}
+
+/// Splits `value` into the two 7-bit bytes understood by `decode_7bit_byte`.
+pub fn encode_7bit_byte(value: u8) -> [u8; 2] {
+    [value & 0x7F, value >> 7]
+}
+
+/// Reassembles a little-endian `u32` from the first 8 bytes of `bytes`.
+///
+/// # Panics
+///
+/// Panics if `bytes` is shorter than 8 bytes.
+pub fn decode_7bit_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(decode_4_bytes(bytes))
+}
+
+/// Encodes `value` as the 8 bytes understood by `decode_7bit_u32`.
+pub fn encode_7bit_u32(value: u32) -> [u8; 8] {
+    encode_4_bytes(value.to_le_bytes())
+}
+
+/// Reassembles a little-endian IEEE-754 `f32` from the first 8 bytes of `bytes`.
+///
+/// # Panics
+///
+/// Panics if `bytes` is shorter than 8 bytes.
+pub fn decode_7bit_float(bytes: &[u8]) -> f32 {
+    f32::from_le_bytes(decode_4_bytes(bytes))
+}
+
+/// Encodes `value` as the 8 bytes understood by `decode_7bit_float`.
+pub fn encode_7bit_float(value: f32) -> [u8; 8] {
+    encode_4_bytes(value.to_le_bytes())
+}
+
+fn decode_4_bytes(bytes: &[u8]) -> [u8; 4] {
+    let mut out = [0u8; 4];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = decode_7bit_byte(&bytes[2*i..]);
+    }
+    out
+}
+
+fn encode_4_bytes(bytes: [u8; 4]) -> [u8; 8] {
+    let mut out = [0u8; 8];
+    for (i, byte) in bytes.iter().enumerate() {
+        out[2*i..2*i+2].copy_from_slice(&encode_7bit_byte(*byte));
+    }
+    out
+}
+
+// Decodes the x, y and z acceleration from a CP_ACCEL_READ_REPLY, or `None`
+// if it's too short.
+pub(crate) fn decode_accel_reply(data: &[u8]) -> Option<(f32, f32, f32)> {
+    if data.len() < 24 {
+        return None;
+    }
+
+    Some((decode_7bit_float(&data[0..8]), decode_7bit_float(&data[8..16]), decode_7bit_float(&data[16..24])))
+}
+
+// Decodes the click register from a CP_ACCEL_TAP_REPLY, or `None` if it's too
+// short.
+pub(crate) fn decode_tap_reply(data: &[u8]) -> Option<u8> {
+    if data.len() < 2 {
+        return None;
+    }
+
+    Some(decode_7bit_byte(&data[0..2]))
+}
+
+// Decodes the pin and reading from a CP_CAP_REPLY, or `None` if it's too
+// short. The reading is sent as a 32-bit two's complement value.
+pub(crate) fn decode_cap_reply(data: &[u8]) -> Option<(u8, i32)> {
+    if data.len() < 9 {
+        return None;
+    }
+
+    Some((data[0], decode_7bit_u32(&data[1..9]) as i32))
+}
+
+// Decodes the red, green and blue channels from a CP_SENSECOLOR_REPLY, or
+// `None` if it's too short.
+pub(crate) fn decode_color_reply(data: &[u8]) -> Option<(u8, u8, u8)> {
+    if data.len() < 6 {
+        return None;
+    }
+
+    Some((decode_7bit_byte(&data[0..2]), decode_7bit_byte(&data[2..4]), decode_7bit_byte(&data[4..6])))
+}
+
+// Decodes the major, minor and bugfix numbers from a CP_IMPL_VERS_REPLY, or
+// `None` if it's too short.
+pub(crate) fn decode_version_reply(data: &[u8]) -> Option<(u8, u8, u8)> {
+    if data.len() < 3 {
+        return None;
+    }
+
+    Some((data[0], data[1], data[2]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Some reply data and what decoding it should give.
+    type Case<T> = (&'static [u8], Option<T>);
+
+    #[test]
+    fn decodes_accel_replies() {
+        let cases: &[Case<(f32, f32, f32)>] = &[
+            // 1.0 is 0x3F800000, -9.5 is 0xC1180000, 0.5 is 0x3F000000.
+            (&[0, 0, 0, 0, 0, 1, 63, 0, 0, 0, 0, 0, 24, 0, 65, 1, 0, 0, 0, 0, 0, 0, 63, 0],
+                Some((1.0, -9.5, 0.5))),
+            (&[0; 24], Some((0.0, 0.0, 0.0))),
+            (&[0; 23], None),
+            (&[], None),
+        ];
+
+        for (data, expected) in cases {
+            assert_eq!(decode_accel_reply(data), *expected, "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn decodes_tap_replies() {
+        let cases: &[Case<u8>] = &[
+            (&[0x10, 0], Some(0x10)),
+            (&[0x30, 0], Some(0x30)),
+            (&[0x7F, 1], Some(0xFF)),
+            (&[0x10], None),
+        ];
+
+        for (data, expected) in cases {
+            assert_eq!(decode_tap_reply(data), *expected, "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn decodes_cap_replies() {
+        let cases: &[Case<(u8, i32)>] = &[
+            // 300 is 0x0000012C.
+            (&[3, 0x2C, 0, 1, 0, 0, 0, 0, 0], Some((3, 300))),
+            // -1 is 0xFFFFFFFF.
+            (&[12, 0x7F, 1, 0x7F, 1, 0x7F, 1, 0x7F, 1], Some((12, -1))),
+            // i32::MIN is 0x80000000.
+            (&[0, 0, 0, 0, 0, 0, 0, 0, 1], Some((0, i32::MIN))),
+            (&[3, 0x2C, 0, 1, 0, 0, 0, 0], None),
+        ];
+
+        for (data, expected) in cases {
+            assert_eq!(decode_cap_reply(data), *expected, "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn decodes_color_replies() {
+        let cases: &[Case<(u8, u8, u8)>] = &[
+            (&[0x7F, 1, 0, 0, 0x40, 0], Some((255, 0, 64))),
+            (&[0x00, 1, 0x7F, 0, 0x01, 0], Some((128, 127, 1))),
+            (&[0x7F, 1, 0, 0, 0x40], None),
+        ];
+
+        for (data, expected) in cases {
+            assert_eq!(decode_color_reply(data), *expected, "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn decodes_version_replies() {
+        let cases: &[Case<(u8, u8, u8)>] = &[
+            (&[1, 0, 0], Some((1, 0, 0))),
+            (&[2, 14, 3, 99], Some((2, 14, 3))),
+            (&[1, 0], None),
+        ];
+
+        for (data, expected) in cases {
+            assert_eq!(decode_version_reply(data), *expected, "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn encoding_then_decoding_gives_the_value_back() {
+        for value in 0..=255u8 {
+            let bytes = encode_7bit_byte(value);
+            assert!(bytes.iter().all(|byte| byte & 0x80 == 0));
+            assert_eq!(decode_7bit_byte(&bytes), value);
+        }
+
+        for &value in [0, 1, 300, 0x7FFF_FFFF, 0x8000_0000, u32::MAX].iter() {
+            assert_eq!(decode_7bit_u32(&encode_7bit_u32(value)), value);
+        }
+
+        for &value in [0.0f32, -0.0, 1.0, -9.80665, 1.0e-20, f32::MAX, f32::INFINITY].iter() {
+            assert_eq!(decode_7bit_float(&encode_7bit_float(value)).to_bits(), value.to_bits());
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn decoding_short_input_panics() {
+        decode_7bit_u32(&[1, 2, 3, 4, 5, 6, 7]);
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::VecDeque;
+
+/// Smooths light sensor readings with a moving average over the last
+/// `window` readings. Until `window` readings have been fed in, the average
+/// is over however many there are so far, so the first few are still
+/// warming up and follow the raw readings more closely.
+#[derive(Debug, Clone)]
+pub struct LightSmoother {
+    window: usize,
+    readings: VecDeque<u16>,
+    total: u32,
+}
+
+impl LightSmoother {
+    /// Creates a new `LightSmoother` averaging the last `window` readings.
+    /// A window of 0 is treated as 1, which doesn't smooth at all.
+    pub fn new(window: usize) -> LightSmoother {
+        let window = window.max(1);
+
+        LightSmoother {
+            window,
+            readings: VecDeque::with_capacity(window),
+            total: 0,
+        }
+    }
+
+    /// Feeds one reading in and returns the average of the readings in the
+    /// window.
+    pub fn update(&mut self, reading: u16) -> f64 {
+        if self.readings.len() == self.window {
+            if let Some(oldest) = self.readings.pop_front() {
+                self.total -= oldest as u32;
+            }
+        }
+
+        self.readings.push_back(reading);
+        self.total += reading as u32;
+
+        self.total as f64 / self.readings.len() as f64
+    }
+
+    /// Returns the number of readings the average is taken over.
+    pub fn window(&self) -> usize {
+        self.window
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_the_last_window_readings() {
+        let mut smoother = LightSmoother::new(3);
+        let smoothed: Vec<f64> = [100, 130, 70, 160, 70].iter().map(|&reading| smoother.update(reading)).collect();
+
+        // The first two are warming up, averaging fewer than three readings.
+        assert_eq!(smoothed, vec![100.0, 115.0, 100.0, 120.0, 100.0]);
+    }
+
+    #[test]
+    fn a_window_of_0_does_not_smooth() {
+        let mut smoother = LightSmoother::new(0);
+
+        assert_eq!(smoother.window(), 1);
+        assert_eq!(smoother.update(5), 5.0);
+        assert_eq!(smoother.update(9), 9.0);
+    }
+}
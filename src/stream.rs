@@ -0,0 +1,379 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::{CircuitPlayground, CpError, CpEvent};
+
+// How many events an `EventStream` holds before dropping new ones.
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// One of the streams the board can send readings on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensorStream {
+    Accel,
+    Tap,
+    Cap(u8),
+}
+
+impl SensorStream {
+    fn start(self, circuit_playground: &mut CircuitPlayground) -> Result<(), CpError> {
+        match self {
+            SensorStream::Accel => circuit_playground.send_stream_command(crate::CP_ACCEL_STREAM_ON, &[]),
+            SensorStream::Tap => circuit_playground.send_stream_command(crate::CP_ACCEL_TAP_STREAM_ON, &[]),
+            SensorStream::Cap(pin) => {
+                crate::check_cap_pin(pin)?;
+                circuit_playground.send_stream_command(crate::CP_CAP_ON, &[pin])
+            }
+        }
+    }
+
+    fn stop(self, circuit_playground: &mut CircuitPlayground) -> Result<(), CpError> {
+        match self {
+            SensorStream::Accel => circuit_playground.stop_accel_stream(),
+            SensorStream::Tap => circuit_playground.stop_tap_stream(),
+            SensorStream::Cap(pin) => circuit_playground.stop_cap_stream(pin),
+        }
+    }
+}
+
+/// A handle to a running stream, returned by `start_accel_stream`,
+/// `start_tap_stream` and `start_cap_stream`. The stream is stopped, and its
+/// callback removed, when the guard is dropped.
+///
+/// The guard borrows the `CircuitPlayground` and dereferences to it, so
+/// callbacks can be registered and `read_and_dispatch` called through it.
+#[must_use = "the stream is stopped as soon as the guard is dropped"]
+pub struct StreamGuard<'a> {
+    circuit_playground: &'a mut CircuitPlayground,
+    stream: SensorStream,
+}
+
+impl<'a> StreamGuard<'a> {
+    // Wraps a stream that has already been started.
+    pub(crate) fn new(circuit_playground: &'a mut CircuitPlayground, stream: SensorStream) -> StreamGuard<'a> {
+        StreamGuard { circuit_playground, stream }
+    }
+
+    /// Stops the stream now, returning any error from sending the command
+    /// instead of ignoring it as dropping the guard does.
+    pub fn stop(self) -> Result<(), CpError> {
+        let result = self.stream.stop(self.circuit_playground);
+        std::mem::forget(self);
+        result
+    }
+}
+
+impl<'a> Deref for StreamGuard<'a> {
+    type Target = CircuitPlayground;
+
+    fn deref(&self) -> &CircuitPlayground {
+        self.circuit_playground
+    }
+}
+
+impl<'a> DerefMut for StreamGuard<'a> {
+    fn deref_mut(&mut self) -> &mut CircuitPlayground {
+        self.circuit_playground
+    }
+}
+
+impl<'a> Drop for StreamGuard<'a> {
+    // Errors can't be returned from here, so they are ignored; use `stop` to
+    // see them.
+    fn drop(&mut self) {
+        let _ = self.stream.stop(self.circuit_playground);
+    }
+}
+
+/// Several streams read on a background thread, returned by
+/// `CircuitPlayground::event_stream`. Every reply from the board is sent to
+/// `receiver` as a `CpEvent` instead of being passed to callbacks.
+///
+/// The channel holds a limited number of events. If the receiver falls
+/// behind and it fills up, new events are dropped rather than letting the
+/// serial line back up, so slow consumers miss readings instead of getting
+/// old ones.
+pub struct EventStream {
+    receiver: Receiver<CpEvent>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<(CircuitPlayground, Option<CpError>)>>,
+    streams: Vec<SensorStream>,
+}
+
+impl EventStream {
+    // Starts `streams` and spawns the thread that reads them.
+    pub(crate) fn spawn(mut circuit_playground: CircuitPlayground, streams: &[SensorStream]) -> Result<EventStream, CpError> {
+        for stream in streams {
+            stream.start(&mut circuit_playground)?;
+        }
+
+        let (sender, receiver) = mpsc::sync_channel(EVENT_CHANNEL_CAPACITY);
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+
+        let thread = thread::spawn(move || {
+            while thread_running.load(Ordering::Relaxed) {
+                let deadline = Instant::now() + circuit_playground.reply_timeout;
+
+                match circuit_playground.next_event(deadline) {
+                    Ok(event) => match sender.try_send(event) {
+                        Ok(()) | Err(TrySendError::Full(_)) => {}
+                        Err(TrySendError::Disconnected(_)) => break,
+                    },
+                    Err(CpError::Timeout) => {}
+                    Err(err) => return (circuit_playground, Some(err)),
+                }
+            }
+            (circuit_playground, None)
+        });
+
+        Ok(EventStream {
+            receiver,
+            running,
+            thread: Some(thread),
+            streams: streams.to_vec(),
+        })
+    }
+
+    /// Returns the channel events arrive on. It disconnects if reading from
+    /// the board fails, and `stop` then returns the error.
+    pub fn receiver(&self) -> &Receiver<CpEvent> {
+        &self.receiver
+    }
+
+    /// Stops the background thread and the streams, and hands back the
+    /// `CircuitPlayground`. This can take up to the reply timeout. If reading
+    /// from the board failed and stopped the thread early, that error is
+    /// returned instead, and the board is dropped.
+    pub fn stop(mut self) -> Result<CircuitPlayground, CpError> {
+        let (mut circuit_playground, err) = self.join();
+
+        if let Some(err) = err {
+            return Err(err);
+        }
+        for stream in &self.streams {
+            stream.stop(&mut circuit_playground)?;
+        }
+        Ok(circuit_playground)
+    }
+
+    // Tells the thread to finish and waits until it hands the board back.
+    fn join(&mut self) -> (CircuitPlayground, Option<CpError>) {
+        self.running.store(false, Ordering::Relaxed);
+
+        match self.thread.take().expect("the event thread was already joined").join() {
+            Ok(stopped) => stopped,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl Drop for EventStream {
+    // Dropping the board it hands back stops everything it was doing.
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            self.join();
+        }
+    }
+}
+
+/// The thermistor read on a background thread every interval, returned by
+/// `CircuitPlayground::on_temperature`. Each temperature is passed to the
+/// callback on that thread, so it must be `Send`, and anything it shares
+/// with the rest of the program needs a `Mutex`, an atomic or a channel.
+/// A slow callback delays the next reading rather than piling them up.
+pub struct TemperatureMonitor {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<(CircuitPlayground, Option<CpError>)>>,
+}
+
+impl TemperatureMonitor {
+    // Spawns the thread that reads the temperature and calls `callback`.
+    pub(crate) fn spawn<F>(mut circuit_playground: CircuitPlayground, interval: Duration, mut callback: F) -> TemperatureMonitor
+        where F: FnMut(f64) + Send + 'static {
+        let (stop, stopped) = mpsc::channel();
+
+        let thread = thread::spawn(move || {
+            loop {
+                match circuit_playground.read_temperature_c() {
+                    Ok(celsius) => callback(celsius),
+                    Err(CpError::Timeout) => {}
+                    Err(err) => return (circuit_playground, Some(err)),
+                }
+
+                // Waiting on the channel rather than sleeping lets `stop`
+                // return without sitting out the rest of the interval.
+                match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            (circuit_playground, None)
+        });
+
+        TemperatureMonitor {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Stops the background thread and hands back the `CircuitPlayground`,
+    /// along with the error that stopped the thread early, if one did. A
+    /// reading in progress is finished first, which can take up to the
+    /// reply timeout.
+    pub fn stop(mut self) -> (CircuitPlayground, Option<CpError>) {
+        self.join()
+    }
+
+    // Tells the thread to finish and waits until it hands the board back.
+    fn join(&mut self) -> (CircuitPlayground, Option<CpError>) {
+        self.stop.take();
+
+        match self.thread.take().expect("the temperature thread was already joined").join() {
+            Ok(stopped) => stopped,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl Drop for TemperatureMonitor {
+    // Dropping the board it hands back stops everything it was doing.
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            self.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use firmata::{ANALOG_MESSAGE, END_SYSEX, START_SYSEX};
+
+    use super::SensorStream;
+    use crate::protocol::{encode_7bit_float, encode_7bit_u32};
+    use crate::{CircuitPlayground, CpError, CpEvent, MockTransport, TapState};
+    use crate::{CP_ACCEL_READ_REPLY, CP_ACCEL_TAP_REPLY, CP_ACCEL_TAP_STREAM_OFF, CP_CAP_OFF, CP_CAP_REPLY, CP_COMMAND, THERM_PIN};
+
+    // A transport whose writes succeed but whose reads fail, as an unplugged
+    // board's do.
+    struct UnpluggedTransport;
+
+    impl io::Read for UnpluggedTransport {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::from(io::ErrorKind::BrokenPipe))
+        }
+    }
+
+    impl io::Write for UnpluggedTransport {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn event_stream_stop_returns_the_error_that_stopped_it() {
+        let cp = CircuitPlayground::with_transport(UnpluggedTransport);
+        let events = cp.event_stream(&[]).unwrap();
+
+        // The channel disconnects once the thread has stopped.
+        assert!(events.receiver().recv().is_err());
+        assert!(matches!(events.stop(), Err(CpError::Io(_))));
+    }
+
+    #[test]
+    fn temperature_monitor_stop_returns_the_error_that_stopped_it() {
+        let cp = CircuitPlayground::with_transport(UnpluggedTransport);
+        let monitor = cp.on_temperature(Duration::from_secs(10), |_| panic!("no reading should arrive"));
+
+        let (cp, err) = monitor.stop();
+        assert!(matches!(err, Some(CpError::Io(_))));
+        assert!(!cp.is_connected());
+    }
+
+    #[test]
+    fn dropping_a_guard_stops_its_stream() {
+        let mock = MockTransport::new();
+        let mut cp = CircuitPlayground::with_transport(mock.clone());
+
+        cp.on_cap(9, |_| {});
+        {
+            let _guard = cp.start_cap_stream(9).unwrap();
+            mock.take_written();
+        }
+
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_CAP_OFF, 9, END_SYSEX]);
+        assert!(cp.cap_callbacks.is_empty());
+    }
+
+    #[test]
+    fn stopping_a_guard_sends_the_stop_once() {
+        let mock = MockTransport::new();
+        let mut cp = CircuitPlayground::with_transport(mock.clone());
+
+        let guard = cp.start_tap_stream().unwrap();
+        mock.take_written();
+        guard.stop().unwrap();
+
+        assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_ACCEL_TAP_STREAM_OFF, END_SYSEX]);
+    }
+
+    #[test]
+    fn event_stream_sends_mixed_replies_as_typed_events() {
+        let mock = MockTransport::new();
+        let cp = CircuitPlayground::with_transport(mock.clone());
+
+        let accel: Vec<u8> = [0.0f32, 1.0, 9.5].iter().flat_map(|&value| encode_7bit_float(value).to_vec()).collect();
+        let mut cap = vec![3];
+        cap.extend_from_slice(&encode_7bit_u32(700));
+        mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &accel);
+        mock.queue_cp_reply(CP_ACCEL_TAP_REPLY, &[0x10, 0]);
+        mock.queue_cp_reply(CP_CAP_REPLY, &cap);
+
+        let events = cp.event_stream(&[SensorStream::Accel, SensorStream::Tap, SensorStream::Cap(3)]).unwrap();
+        let received: Vec<CpEvent> = (0..3)
+            .map(|_| events.receiver().recv_timeout(Duration::from_secs(1)).unwrap())
+            .collect();
+
+        assert_eq!(received, vec![
+            CpEvent::Accel(0.0, 1.0, 9.5),
+            CpEvent::Tap(TapState { single: true, double: false }),
+            CpEvent::Cap(3, 700),
+        ]);
+        assert!(events.stop().is_ok());
+    }
+
+    #[test]
+    fn temperature_monitor_calls_back_every_interval() {
+        let mock = MockTransport::new();
+        let mut cp = CircuitPlayground::with_transport(mock.clone());
+        cp.set_reply_timeout(Duration::from_millis(20));
+
+        // 512 reads 25C, and 0, a disconnected thermistor, reads NaN.
+        for &(lsb, msb) in [(0, 4), (0, 4), (0, 0)].iter() {
+            mock.queue_reply(&[ANALOG_MESSAGE | THERM_PIN, lsb, msb]);
+        }
+
+        let (readings, received) = mpsc::channel();
+        let monitor = cp.on_temperature(Duration::from_millis(10), move |celsius| readings.send(celsius).unwrap());
+
+        let celsius: Vec<f64> = (0..3).map(|_| received.recv_timeout(Duration::from_secs(1)).unwrap()).collect();
+        assert!(celsius[..2].iter().all(|&c| (c - 24.956).abs() < 0.001), "{:?}", celsius);
+        assert!(celsius[2].is_nan());
+
+        // Once the replies run out the readings time out and are skipped.
+        let (_cp, err) = monitor.stop();
+        assert!(err.is_none());
+        assert!(received.try_recv().is_err());
+    }
+}
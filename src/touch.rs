@@ -0,0 +1,82 @@
+/// A change in the touch state of a capacitive input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TouchEvent {
+    Pressed,
+    Released,
+}
+
+/// Turns noisy capacitive readings into `Pressed` and `Released` events,
+/// only reporting a change once the reading has stayed on the new side of
+/// the threshold for a number of samples in a row.
+#[derive(Debug, Clone)]
+pub struct TouchDebouncer {
+    threshold: i32,
+    samples: u32,
+    pressed: bool,
+    count: u32,
+}
+
+impl TouchDebouncer {
+    /// Creates a new `TouchDebouncer` that counts readings above `threshold`
+    /// as touched and needs `samples` readings in a row to change state.
+    pub fn new(threshold: i32, samples: u32) -> TouchDebouncer {
+        TouchDebouncer {
+            threshold,
+            samples,
+            pressed: false,
+            count: 0,
+        }
+    }
+
+    /// Feeds one cap touch reading in, returning an event if the debounced
+    /// state changed.
+    pub fn update(&mut self, value: i32) -> Option<TouchEvent> {
+        if (value > self.threshold) == self.pressed {
+            self.count = 0;
+            return None;
+        }
+
+        self.count += 1;
+
+        if self.count < self.samples {
+            return None;
+        }
+
+        self.count = 0;
+        self.pressed = !self.pressed;
+
+        Some(if self.pressed { TouchEvent::Pressed } else { TouchEvent::Released })
+    }
+
+    /// Returns whether the input is currently considered touched.
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_bouncy_touch_is_one_press() {
+        let mut debouncer = TouchDebouncer::new(300, 3);
+        let readings = [100, 400, 100, 400, 400, 100, 400, 400, 400, 400, 350, 100, 400, 500];
+
+        let events: Vec<TouchEvent> = readings.iter().filter_map(|&value| debouncer.update(value)).collect();
+        assert_eq!(events, vec![TouchEvent::Pressed]);
+        assert!(debouncer.is_pressed());
+    }
+
+    #[test]
+    fn release_is_debounced_too() {
+        let mut debouncer = TouchDebouncer::new(300, 2);
+
+        assert_eq!(debouncer.update(400), None);
+        assert_eq!(debouncer.update(400), Some(TouchEvent::Pressed));
+        assert_eq!(debouncer.update(100), None);
+        assert_eq!(debouncer.update(400), None);
+        assert_eq!(debouncer.update(100), None);
+        assert_eq!(debouncer.update(100), Some(TouchEvent::Released));
+    }
+}
@@ -0,0 +1,49 @@
+#![cfg(feature = "async")]
+
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use circuit_playground_firmata::protocol::encode_7bit_float;
+use circuit_playground_firmata::{AsyncCircuitPlayground, CircuitPlayground, MockTransport};
+use circuit_playground_firmata::{CP_ACCEL_READ, CP_ACCEL_READ_REPLY, CP_COMMAND};
+use firmata::{END_SYSEX, START_SYSEX};
+
+// Wakes a task by unparking the thread running it.
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// Runs `future` to completion on this thread, the least an executor can do.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn awaits_a_canned_accelerometer_reply() {
+    let mock = MockTransport::new();
+    let board = AsyncCircuitPlayground::new(CircuitPlayground::with_transport(mock.clone()));
+
+    let mut reply = vec![];
+    for &value in [0.5, -1.0, 9.8].iter() {
+        reply.extend_from_slice(&encode_7bit_float(value));
+    }
+    mock.queue_cp_reply(CP_ACCEL_READ_REPLY, &reply);
+
+    assert_eq!(block_on(board.read_accelerometer()).unwrap(), (0.5, -1.0, 9.8));
+    assert_eq!(mock.take_written(), vec![START_SYSEX, CP_COMMAND, CP_ACCEL_READ, END_SYSEX]);
+}